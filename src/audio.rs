@@ -1,24 +1,45 @@
+use crate::dsp::{AudioEffect, EffectChain, EqualizerBand};
+use crate::focus::DUCK_GAIN;
+use crate::gain::{balance_gains, Ramp};
+use crate::meter::{AudioLevels, Meter};
+use crate::queue::{timestamp, Decoded};
+use crate::ring::{ring_buffer, Consumer, Producer};
+use crate::sink::{AudioSink, ThreadSink};
+use crate::stretch::TimeStretch;
+use av_data::frame::AudioInfo as FrameAudioInfo;
 use av_data::frame::{ArcFrame, FrameBufferConv, MediaKind};
 use av_data::params::AudioInfo;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Device, Format, SampleFormat, SampleRate, Shape, Stream};
-use std::sync::mpsc::Receiver;
+use cpal::{Device, Format, Sample, SampleFormat, SampleRate, Stream};
+use crossbeam::atomic::AtomicCell;
+use crossbeam::channel::Sender;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum AudioError {
     NoOutputDevice,
+    DeviceNotFound(String),
+    Devices(cpal::DevicesError),
+    DeviceName(cpal::DeviceNameError),
     SupportedFormats(cpal::SupportedFormatsError),
     FormatNotSupported(Format),
     BuildStream(cpal::BuildStreamError),
     PlayStream(cpal::PlayStreamError),
     PauseStream(cpal::PauseStreamError),
+    Io(std::io::Error),
 }
 
 impl std::fmt::Display for AudioError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let msg = match self {
             Self::NoOutputDevice => "no output device available",
+            Self::DeviceNotFound(name) => return write!(f, "output device {} not found", name),
+            Self::Devices(err) => return err.fmt(f),
+            Self::DeviceName(err) => return err.fmt(f),
             Self::SupportedFormats(err) => return err.fmt(f),
             Self::FormatNotSupported(format) => {
                 return write!(f, "format {:?} not supported", format)
@@ -26,6 +47,7 @@ impl std::fmt::Display for AudioError {
             Self::BuildStream(err) => return err.fmt(f),
             Self::PlayStream(err) => return err.fmt(f),
             Self::PauseStream(err) => return err.fmt(f),
+            Self::Io(err) => return err.fmt(f),
         };
         write!(f, "{}", msg)
     }
@@ -33,6 +55,18 @@ impl std::fmt::Display for AudioError {
 
 impl std::error::Error for AudioError {}
 
+impl From<cpal::DevicesError> for AudioError {
+    fn from(error: cpal::DevicesError) -> Self {
+        Self::Devices(error)
+    }
+}
+
+impl From<cpal::DeviceNameError> for AudioError {
+    fn from(error: cpal::DeviceNameError) -> Self {
+        Self::DeviceName(error)
+    }
+}
+
 impl From<cpal::SupportedFormatsError> for AudioError {
     fn from(error: cpal::SupportedFormatsError) -> Self {
         Self::SupportedFormats(error)
@@ -57,115 +91,1240 @@ impl From<cpal::PauseStreamError> for AudioError {
     }
 }
 
+impl From<std::io::Error> for AudioError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// How much decoded audio is buffered ahead of the device callback.
+const BUFFER_DURATION_MS: usize = 500;
+
+/// How often the worker checks for new frames, buffer space and state changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the worker checks whether the default output device changed.
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Length of the fade applied when starting and stopping playback.
+const FADE_DURATION_MS: u64 = 15;
+
+/// Length of the fades out of the samples discarded by a seek and into the
+/// ones following it.
+const FLUSH_FADE_DURATION_MS: u64 = 5;
+
+/// Default duration of volume and mute changes.
+const DEFAULT_RAMP_DURATION_MS: u64 = 50;
+
+/// How much played audio is kept for metering before the worker picks it up.
+const ANALYSIS_BUFFER_DURATION_MS: usize = 200;
+
+/// Frames the device callback renders at once. Larger requests are rendered
+/// in parts so the callback never allocates.
+const RENDER_FRAMES: usize = 4096;
+
+/// Chunks the ring buffer can hold, far more than decoded frames fit into
+/// the buffered duration.
+const CHUNK_CAPACITY: usize = 1024;
+
+/// Samples pushed to the ring buffer at once and the source frames they were
+/// stretched from, so the callback advances the media clock by the speed the
+/// samples were stretched with rather than the current one.
+#[derive(Clone, Copy, Debug, Default)]
+struct Chunk {
+    /// Interleaved output samples.
+    samples: usize,
+    /// Decoded frames, fractional once partly played.
+    frames: f64,
+}
+
+/// Events reported by an `AudioStream`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioEvent {
+    /// The buffer drained and the device is being fed silence.
+    BufferingStart,
+    /// The buffer has been refilled after a `BufferingStart`.
+    BufferingEnd,
+    /// Playback moved to the named device after the previous one failed or
+    /// the default device changed.
+    DeviceChanged(String),
+    /// Levels of the audio played since the previous `Levels` event, sent
+    /// while metering is enabled.
+    Levels(AudioLevels),
+    /// The decoder reached the end of the file and every buffered sample was
+    /// played.
+    Ended,
+}
+
+/// Counters describing how well the decoder keeps up with the device.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AudioStats {
+    /// Number of device callbacks that couldn't be filled completely.
+    pub underruns: u64,
+    /// Number of samples replaced with silence.
+    pub silent_samples: u64,
+}
+
+/// State shared between the `AudioStream`, its worker and the device callback.
+struct Shared {
+    volume: AtomicCell<f64>,
+    /// Stereo balance from `-1.0` (left) to `1.0` (right).
+    balance: AtomicCell<f32>,
+    muted: AtomicBool,
+    /// Set while another player ducks this one.
+    ducked: AtomicBool,
+    /// Set when loudness normalization is enabled.
+    normalize: AtomicBool,
+    /// Linear loudness normalization gain.
+    normalization_gain: AtomicCell<f64>,
+    /// Duration of volume and mute ramps in milliseconds.
+    ramp_duration: AtomicU64,
+    /// Set while playing, the callback fades in while set and out otherwise.
+    active: AtomicBool,
+    /// Set by the callback once it has completely faded out.
+    silent: AtomicBool,
+    /// Set by the callback while it outputs silence because the buffer is empty.
+    starved: AtomicBool,
+    /// Set once the decoder reached the end, an empty buffer is expected from
+    /// then on.
+    eof: AtomicBool,
+    /// Set once every sample up to the end was played.
+    ended: AtomicBool,
+    /// Set by the worker after a seek, the callback then discards the
+    /// buffered samples and continues the clock at `flush_position`.
+    flush: AtomicBool,
+    /// Source frame the clock continues at after a flush.
+    flush_position: AtomicU64,
+    /// Set when the `AudioStream` is dropped to stop the worker.
+    closed: AtomicBool,
+    /// Set by the stream error callback, the worker then reopens the output.
+    stream_error: AtomicBool,
+    /// Playback speed, audio is time stretched and the clock scaled by it.
+    speed: AtomicCell<f64>,
+    /// Set while the callback copies the played samples for metering.
+    metering: AtomicBool,
+    /// Interval between `Levels` events in milliseconds.
+    metering_interval: AtomicU64,
+    /// Number of source frames played, this is the media clock and survives
+    /// switching devices.
+    played: AtomicU64,
+    underruns: AtomicU64,
+    silent_samples: AtomicU64,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            volume: AtomicCell::new(1.0),
+            balance: AtomicCell::new(0.0),
+            muted: AtomicBool::new(false),
+            ducked: AtomicBool::new(false),
+            normalize: AtomicBool::new(false),
+            normalization_gain: AtomicCell::new(1.0),
+            ramp_duration: AtomicU64::new(DEFAULT_RAMP_DURATION_MS),
+            active: AtomicBool::new(false),
+            silent: AtomicBool::new(true),
+            starved: AtomicBool::new(false),
+            eof: AtomicBool::new(false),
+            ended: AtomicBool::new(false),
+            flush: AtomicBool::new(false),
+            flush_position: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            stream_error: AtomicBool::new(false),
+            speed: AtomicCell::new(1.0),
+            metering: AtomicBool::new(false),
+            metering_interval: AtomicU64::new(0),
+            played: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            silent_samples: AtomicU64::new(0),
+        }
+    }
+}
+
+/// An output device as reported by `output_devices`.
+#[derive(Clone, Debug)]
+pub struct AudioDevice {
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// Lists the output devices of the default host.
+pub fn output_devices() -> Result<Vec<AudioDevice>, AudioError> {
+    let host = cpal::default_host();
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+    let mut devices = Vec::new();
+    for device in host.output_devices()? {
+        let name = device.name()?;
+        devices.push(AudioDevice {
+            is_default: Some(&name) == default_name.as_ref(),
+            name,
+        });
+    }
+    Ok(devices)
+}
+
+/// Looks up an output device by name, or the default device if no name is given.
+fn find_device(name: Option<&str>) -> Result<Device, AudioError> {
+    let host = cpal::default_host();
+    let name = match name {
+        Some(name) => name,
+        None => {
+            return host
+                .default_output_device()
+                .ok_or(AudioError::NoOutputDevice)
+        }
+    };
+    for device in host.output_devices()? {
+        if device.name()? == name {
+            return Ok(device);
+        }
+    }
+    Err(AudioError::DeviceNotFound(name.to_string()))
+}
+
+/// A device stream or a sink playing on its own thread.
+enum OutputStream {
+    Device(Stream),
+    Thread(ThreadSink),
+}
+
+impl OutputStream {
+    fn play(&self) -> Result<(), AudioError> {
+        match self {
+            Self::Device(stream) => stream.play()?,
+            Self::Thread(sink) => sink.play(),
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> Result<(), AudioError> {
+        match self {
+            Self::Device(stream) => stream.pause()?,
+            Self::Thread(sink) => sink.pause(),
+        }
+        Ok(())
+    }
+}
+
+/// The device stream together with everything needed to rebuild it.
+struct Output {
+    stream: Mutex<OutputStream>,
+    renderer: Arc<Mutex<Renderer>>,
+    shared: Arc<Shared>,
+    info: AudioInfo,
+    sink: AudioSink,
+    /// Device selected with `set_device`, `None` follows the default device.
+    requested: Mutex<Option<String>>,
+    /// Name of the device currently playing.
+    current: Mutex<String>,
+    playing: AtomicBool,
+}
+
+impl Output {
+    /// Replaces the stream with one on `device` and returns the device name.
+    ///
+    /// Buffered samples and the played frame count are kept, so playback
+    /// continues where it left off.
+    fn open(&self, device: Option<&str>) -> Result<String, AudioError> {
+        let player = AudioPlayer::new(&self.info, &self.sink, device)?;
+        let name = player.name()?;
+        // The old stream keeps playing if the new one can't be built.
+        let new_stream = player.build_stream(self.renderer.clone(), self.shared.clone())?;
+        let mut stream = self.stream.lock().unwrap();
+        stream.pause().ok();
+        *stream = new_stream;
+        if self.playing.load(Ordering::Relaxed) {
+            stream.play()?;
+        } else {
+            stream.pause()?;
+        }
+        *self.current.lock().unwrap() = name.clone();
+        Ok(name)
+    }
+
+    /// Reopens the requested device, falling back to the default device if it
+    /// is gone.
+    fn reopen(&self) -> Result<String, AudioError> {
+        let requested = self.requested.lock().unwrap().clone();
+        match self.open(requested.as_deref()) {
+            Err(err) if requested.is_some() => {
+                log::warn!("{}, falling back to the default device", err);
+                self.open(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Whether the stream should move because the default device changed.
+    fn default_changed(&self) -> bool {
+        if !self.sink.is_device() || self.requested.lock().unwrap().is_some() {
+            return false;
+        }
+        match find_device(None).and_then(|device| device.name().map_err(AudioError::from)) {
+            Ok(name) => *self.current.lock().unwrap() != name,
+            Err(_) => false,
+        }
+    }
+}
+
+unsafe impl Send for Output {}
+unsafe impl Sync for Output {}
+
 pub struct AudioStream {
-    stream: Arc<Mutex<Stream>>,
-    volume: Arc<Mutex<f64>>,
+    output: Arc<Output>,
+    shared: Arc<Shared>,
+    effects: Arc<Mutex<EffectChain>>,
+    worker: Option<JoinHandle<()>>,
 }
 
 impl AudioStream {
     pub fn play(&self) -> Result<(), AudioError> {
-        self.stream.lock().unwrap().play()?;
+        self.shared.active.store(true, Ordering::Relaxed);
+        self.output.stream.lock().unwrap().play()?;
+        self.output.playing.store(true, Ordering::Relaxed);
         Ok(())
     }
 
     pub fn pause(&self) -> Result<(), AudioError> {
-        self.stream.lock().unwrap().pause()?;
+        if self.output.playing.load(Ordering::Relaxed) {
+            self.fade_out();
+        }
+        self.output.stream.lock().unwrap().pause()?;
+        self.output.playing.store(false, Ordering::Relaxed);
         Ok(())
     }
 
+    /// Fades out and waits until the callback has gone silent, so the stream
+    /// can be stopped without a click.
+    fn fade_out(&self) {
+        self.shared.active.store(false, Ordering::Relaxed);
+        let deadline = Instant::now() + Duration::from_millis(FADE_DURATION_MS * 4);
+        while !self.shared.silent.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
     pub fn set_volume(&self, volume: f64) {
-        *self.volume.lock().unwrap() = volume;
+        self.shared.volume.store(volume);
+    }
+
+    /// Pans the first two channels, combines with the volume.
+    pub fn set_balance(&self, balance: f32) {
+        self.shared.balance.store(balance);
+    }
+
+    pub fn set_normalization(&self, enabled: bool) {
+        self.shared.normalize.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets the loudness normalization gain in dB, applied before the effect
+    /// chain while normalization is enabled so the limiter catches boosts.
+    pub fn set_normalization_gain(&self, gain: f64) {
+        let gain = 10f64.powf(gain / 20.0);
+        self.shared.normalization_gain.store(gain);
+    }
+
+    pub fn set_equalizer_bands(&self, bands: Vec<EqualizerBand>) {
+        self.effects.lock().unwrap().set_equalizer_bands(bands);
+    }
+
+    pub fn set_ducked(&self, ducked: bool) {
+        self.shared.ducked.store(ducked, Ordering::Relaxed);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.shared.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Sets how long volume and mute changes take.
+    pub fn set_ramp_duration(&self, duration: Duration) {
+        let millis = duration.as_millis() as u64;
+        self.shared.ramp_duration.store(millis, Ordering::Relaxed);
+    }
+
+    /// Moves playback to another output device, or the default device if
+    /// `device` is `None`.
+    ///
+    /// Buffered samples are kept, so playback continues where it left off.
+    /// Does nothing if the stream doesn't play to a device.
+    pub fn set_device(&self, device: Option<&str>) -> Result<(), AudioError> {
+        if !self.output.sink.is_device() {
+            return Ok(());
+        }
+        self.output.open(device)?;
+        *self.output.requested.lock().unwrap() = device.map(str::to_string);
+        Ok(())
+    }
+
+    /// Enables `Levels` events, sent every `interval` while playing.
+    pub fn set_metering(&self, enabled: bool, interval: Duration) {
+        let millis = interval.as_millis() as u64;
+        self.shared
+            .metering_interval
+            .store(millis, Ordering::Relaxed);
+        self.shared.metering.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Changes the playback speed while keeping the pitch.
+    pub fn set_speed(&self, speed: f64) {
+        self.shared.speed.store(speed);
+    }
+
+    /// Position of the media clock.
+    pub fn position(&self) -> Duration {
+        let played = if self.shared.flush.load(Ordering::Acquire) {
+            self.shared.flush_position.load(Ordering::Relaxed)
+        } else {
+            self.shared.played.load(Ordering::Relaxed)
+        };
+        let rate = self.output.info.rate.max(1) as u64;
+        Duration::from_nanos(played * 1_000_000_000 / rate)
+    }
+
+    /// Whether every sample up to the end of the file was played.
+    pub fn is_ended(&self) -> bool {
+        self.shared.ended.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        AudioStats {
+            underruns: self.shared.underruns.load(Ordering::Relaxed),
+            silent_samples: self.shared.silent_samples.load(Ordering::Relaxed),
+        }
     }
 }
 
-unsafe impl Send for AudioStream {}
-unsafe impl Sync for AudioStream {}
+impl Drop for AudioStream {
+    /// Stops the worker and waits for it, the output is closed once the
+    /// worker released it.
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            worker.join().ok();
+        }
+    }
+}
+
+/// Device sample formats in order of preference.
+///
+/// `f32` keeps full precision through volume scaling, the integer formats are
+/// only used when the device doesn't support floating point output.
+const SAMPLE_FORMATS: [SampleFormat; 3] = [SampleFormat::F32, SampleFormat::I16, SampleFormat::U16];
 
 pub struct AudioPlayer {
-    device: Device,
-    shape: Shape,
+    sink: AudioSink,
+    /// `None` unless playing to a device.
+    device: Option<Device>,
+    format: Format,
+    info: AudioInfo,
 }
 
 impl AudioPlayer {
-    pub fn new(audio: &AudioInfo) -> Result<Self, AudioError> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or(AudioError::NoOutputDevice)?;
-        let format = Format {
+    /// Creates a player for `sink`, `device` selects the output device of
+    /// device sinks.
+    pub fn new(
+        audio: &AudioInfo,
+        sink: &AudioSink,
+        device: Option<&str>,
+    ) -> Result<Self, AudioError> {
+        let mut format = Format {
             channels: audio.map.as_ref().map(|m| m.len() as _).unwrap_or_default(),
             sample_rate: SampleRate(audio.rate as _),
-            data_type: SampleFormat::I16,
+            data_type: SAMPLE_FORMATS[0],
         };
-        let supported_formats = device.supported_output_formats()?;
-        let mut supported = false;
-        for supported_format in supported_formats {
-            if supported_format.min_sample_rate <= format.sample_rate
-                && supported_format.max_sample_rate >= format.sample_rate
-                && supported_format.channels == format.channels
-                && supported_format.data_type == format.data_type
-            {
-                supported = true;
-                break;
-            }
-        }
-        if !supported {
-            return Err(AudioError::FormatNotSupported(format));
+        if !sink.is_device() {
+            return Ok(Self {
+                sink: sink.clone(),
+                device: None,
+                format,
+                info: audio.clone(),
+            });
         }
+
+        let device = find_device(device)?;
+        let supported_types: Vec<SampleFormat> = device
+            .supported_output_formats()?
+            .filter(|supported_format| {
+                supported_format.min_sample_rate <= format.sample_rate
+                    && supported_format.max_sample_rate >= format.sample_rate
+                    && supported_format.channels == format.channels
+            })
+            .map(|supported_format| supported_format.data_type)
+            .collect();
+        format.data_type = match SAMPLE_FORMATS
+            .iter()
+            .find(|data_type| supported_types.contains(data_type))
+        {
+            Some(data_type) => *data_type,
+            None => return Err(AudioError::FormatNotSupported(format)),
+        };
+        log::debug!("using audio output format {:?}", format);
         Ok(Self {
-            device,
-            shape: format.shape(),
+            sink: sink.clone(),
+            device: Some(device),
+            format,
+            info: audio.clone(),
         })
     }
 
-    pub fn create_stream(&self, rx: Receiver<ArcFrame>) -> Result<AudioStream, AudioError> {
-        let volume = Arc::new(Mutex::new(1.0));
-        let volume2 = volume.clone();
-        let mut frame = None;
-        let mut in_off = 0;
-        let stream = self.device.build_output_stream::<i16, _, _>(
-            &self.shape,
+    /// Name of the output device, or of the sink if it doesn't play to one.
+    fn name(&self) -> Result<String, AudioError> {
+        match (&self.device, &self.sink) {
+            (Some(device), _) => Ok(device.name()?),
+            (None, AudioSink::Wav { path, .. }) => Ok(path.display().to_string()),
+            (None, _) => Ok("null".to_string()),
+        }
+    }
+
+    pub fn create_stream(
+        self,
+        rx: Receiver<Decoded>,
+        events: Sender<AudioEvent>,
+        effects: Vec<Box<dyn AudioEffect>>,
+    ) -> Result<AudioStream, AudioError> {
+        let rate = self.format.sample_rate.0 as usize;
+        let channels = self.format.channels as usize;
+        let (producer, consumer) = ring_buffer(rate * channels * BUFFER_DURATION_MS / 1000);
+        let (chunks, chunk_consumer) = ring_buffer(CHUNK_CAPACITY);
+        let (analysis, analysis_consumer) =
+            ring_buffer(rate * channels * ANALYSIS_BUFFER_DURATION_MS / 1000);
+        let shared = Arc::new(Shared::new());
+        let renderer = Renderer::new(
+            consumer,
+            chunk_consumer,
+            analysis,
+            shared.clone(),
+            channels,
+            rate,
+        );
+        let renderer = Arc::new(Mutex::new(renderer));
+        let stream = self.build_stream(renderer.clone(), shared.clone())?;
+        let output = Arc::new(Output {
+            stream: Mutex::new(stream),
+            renderer,
+            shared: shared.clone(),
+            current: Mutex::new(self.name()?),
+            info: self.info,
+            sink: self.sink,
+            requested: Mutex::new(None),
+            playing: AtomicBool::new(false),
+        });
+        let worker_output = output.clone();
+        let effects = Arc::new(Mutex::new(EffectChain::new(effects, channels, rate)));
+        let worker_effects = effects.clone();
+        let stretch = TimeStretch::new(channels, rate);
+        let analysis = Analysis {
+            consumer: analysis_consumer,
+            meter: None,
+            channels,
+            rate,
+            buffer: Vec::new(),
+        };
+        let worker = thread::spawn(move || {
+            run_worker(
+                rx,
+                producer,
+                chunks,
+                worker_effects,
+                stretch,
+                analysis,
+                worker_output,
+                events,
+            )
+        });
+        Ok(AudioStream {
+            output,
+            shared,
+            effects,
+            worker: Some(worker),
+        })
+    }
+
+    fn build_stream(
+        &self,
+        renderer: Arc<Mutex<Renderer>>,
+        shared: Arc<Shared>,
+    ) -> Result<OutputStream, AudioError> {
+        let device = match &self.device {
+            Some(device) => device,
+            None => return self.build_thread_sink(renderer),
+        };
+        let stream = match self.format.data_type {
+            SampleFormat::F32 => self.build_typed_stream::<f32>(device, renderer, shared),
+            SampleFormat::I16 => self.build_typed_stream::<i16>(device, renderer, shared),
+            SampleFormat::U16 => self.build_typed_stream::<u16>(device, renderer, shared),
+        }?;
+        Ok(OutputStream::Device(stream))
+    }
+
+    fn build_thread_sink(
+        &self,
+        renderer: Arc<Mutex<Renderer>>,
+    ) -> Result<OutputStream, AudioError> {
+        let realtime = match self.sink {
+            AudioSink::Null { realtime } | AudioSink::Wav { realtime, .. } => realtime,
+            AudioSink::Device => true,
+        };
+        let channels = self.format.channels as usize;
+        let rate = self.format.sample_rate.0 as usize;
+        let sink = ThreadSink::new(&self.sink, channels, rate, move |buffer| {
+            let mut renderer = renderer.lock().unwrap();
+            if realtime {
+                renderer.render(buffer);
+                buffer.len()
+            } else {
+                renderer.render_buffered(buffer)
+            }
+        })?;
+        Ok(OutputStream::Thread(sink))
+    }
+
+    fn build_typed_stream<T: Sample + 'static>(
+        &self,
+        device: &Device,
+        renderer: Arc<Mutex<Renderer>>,
+        shared: Arc<Shared>,
+    ) -> Result<Stream, AudioError> {
+        let stream = device.build_output_stream::<T, _, _>(
+            &self.format.shape(),
             move |buffer| {
-                let volume = { *volume.lock().unwrap() };
-                let mut out_len = buffer.len();
-                let mut out_off = 0;
-                while out_len > 0 {
-                    if frame.is_none() {
-                        if let Ok(f) = rx.recv() {
-                            frame = Some(f);
-                            in_off = 0;
-                        }
-                    }
-                    if let Some(f) = frame.as_ref() {
-                        if let MediaKind::Audio(info) = &f.kind {
-                            let samples = info.samples * info.map.len();
-                            let data: &[i16] = f.buf.as_slice(0).unwrap();
-                            let in_len = samples - in_off;
-                            let len = out_len.min(in_len);
-
-                            for (out_i, in_i) in (out_off..out_off + len).zip(in_off..in_off + len)
-                            {
-                                buffer[out_i] = (data[in_i] as f64 * volume) as i16;
-                            }
-
-                            in_off += len;
-                            out_off += len;
-                            out_len -= len;
-
-                            if in_len == len {
-                                frame = None;
-                            }
+                // The renderer is only contended while the stream is being
+                // replaced, output silence instead of waiting for it.
+                match renderer.try_lock() {
+                    Ok(mut renderer) => renderer.render(buffer),
+                    Err(_) => {
+                        for sample in buffer.iter_mut() {
+                            *sample = T::from(&0.0f32);
                         }
                     }
                 }
             },
-            |error| {
-                eprintln!("{}", error);
+            move |error| {
+                log::error!("audio stream error: {}", error);
+                shared.stream_error.store(true, Ordering::Relaxed);
             },
         )?;
-        Ok(AudioStream {
-            stream: Arc::new(Mutex::new(stream)),
-            volume: volume2,
-        })
+        Ok(stream)
+    }
+}
+
+/// Computes levels from the samples copied by the device callback.
+struct Analysis {
+    consumer: Consumer<f32>,
+    /// Present while metering is enabled.
+    meter: Option<Meter>,
+    channels: usize,
+    rate: usize,
+    buffer: Vec<f32>,
+}
+
+impl Analysis {
+    /// Meters the samples played since the last call, returns the levels of
+    /// the last completed interval if one completed.
+    fn update(&mut self, shared: &Shared) -> Option<AudioLevels> {
+        if !shared.metering.load(Ordering::Relaxed) {
+            self.meter = None;
+            return None;
+        }
+        let millis = shared.metering_interval.load(Ordering::Relaxed) as usize;
+        let interval = (self.rate * millis / 1000).max(1);
+        let meter = match &mut self.meter {
+            Some(meter) if meter.interval() == interval => meter,
+            _ => self
+                .meter
+                .insert(Meter::new(self.channels, self.rate, interval)),
+        };
+        let mut levels = None;
+        self.buffer.resize(4096 * self.channels, 0.0);
+        loop {
+            let read = self.consumer.pop_slice(&mut self.buffer);
+            if read == 0 {
+                break;
+            }
+            levels = meter.process(&self.buffer[..read]).or(levels);
+        }
+        levels
+    }
+}
+
+/// Moves decoded frames into the ring buffer, reports buffering changes and
+/// moves the output to another device when the current one fails.
+///
+/// Runs until the `AudioStream` is dropped.
+fn run_worker(
+    rx: Receiver<Decoded>,
+    mut producer: Producer<f32>,
+    mut chunks: Producer<Chunk>,
+    effects: Arc<Mutex<EffectChain>>,
+    mut stretch: TimeStretch,
+    mut analysis: Analysis,
+    output: Arc<Output>,
+    events: Sender<AudioEvent>,
+) {
+    let shared = output.shared.clone();
+    let mut decoded = Vec::new();
+    let mut samples = Vec::new();
+    let mut offset = 0;
+    let channels = stretch.channels();
+    let ramp_frames = output.info.rate * DEFAULT_RAMP_DURATION_MS as usize / 1000;
+    let mut normalization = Ramp::new(1.0);
+    // Chunk of `samples` waiting for room in `chunks`.
+    let mut chunk = None;
+    // Frames the stretcher held back without output yet.
+    let mut held_frames = 0.0;
+    let mut buffering = false;
+    let mut reopen = false;
+    // A reopen failed, it is retried on the next device poll.
+    let mut retry = false;
+    let mut skip_until = None;
+    let mut device_poll = Instant::now();
+    while !shared.closed.load(Ordering::Relaxed) {
+        let starved = shared.starved.load(Ordering::Relaxed);
+        if starved != buffering {
+            buffering = starved;
+            let event = if buffering {
+                AudioEvent::BufferingStart
+            } else {
+                AudioEvent::BufferingEnd
+            };
+            events.send(event).ok();
+        }
+
+        if shared.stream_error.swap(false, Ordering::Relaxed) {
+            reopen = true;
+        }
+        let polled = device_poll.elapsed() >= DEVICE_POLL_INTERVAL;
+        if polled {
+            device_poll = Instant::now();
+            reopen = reopen || output.default_changed();
+        }
+        if reopen && (polled || !retry) {
+            match output.reopen() {
+                Ok(name) => {
+                    log::info!("audio output moved to {}", name);
+                    reopen = false;
+                    retry = false;
+                    events.send(AudioEvent::DeviceChanged(name)).ok();
+                }
+                Err(err) => {
+                    log::error!("failed to reopen audio output: {}", err);
+                    retry = true;
+                }
+            }
+        }
+
+        if let Some(levels) = analysis.update(&shared) {
+            events.send(AudioEvent::Levels(levels)).ok();
+        }
+
+        // Samples from after a seek wait until the callback discarded the
+        // ones from before.
+        if shared.flush.load(Ordering::Acquire) {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        if let Some(pending) = chunk {
+            if chunks.push_slice(&[pending]) == 0 {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            chunk = None;
+        }
+        if offset < samples.len() {
+            let written = producer.push_slice(&samples[offset..]);
+            offset += written;
+            if written == 0 {
+                thread::sleep(POLL_INTERVAL);
+            }
+            continue;
+        }
+
+        // Keep receiving at the end, a seek may follow.
+        let eof = shared.eof.load(Ordering::Relaxed);
+        if eof && !shared.ended.load(Ordering::Relaxed) && producer.len() == 0 {
+            shared.ended.store(true, Ordering::Relaxed);
+            events.send(AudioEvent::Ended).ok();
+        }
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Decoded::Frame(frame)) => {
+                read_samples(&frame, &mut decoded);
+                if let Some(time) = skip_until.take() {
+                    // Resume at the frame reaching the seek target.
+                    let skipped = samples_before(&frame, time);
+                    if skipped >= decoded.len() {
+                        skip_until = Some(time);
+                    }
+                    decoded.drain(..skipped.min(decoded.len()));
+                }
+                let gain = if shared.normalize.load(Ordering::Relaxed) {
+                    shared.normalization_gain.load()
+                } else {
+                    1.0
+                };
+                normalization.set_target(gain as f32, ramp_frames);
+                for frame in decoded.chunks_mut(channels) {
+                    let gain = normalization.next();
+                    for sample in frame {
+                        *sample *= gain;
+                    }
+                }
+                effects.lock().unwrap().process(&mut decoded);
+                stretch.set_speed(shared.speed.load());
+                samples.clear();
+                stretch.process(&decoded, &mut samples);
+                offset = 0;
+                held_frames += (decoded.len() / channels) as f64;
+                if !samples.is_empty() {
+                    chunk = Some(Chunk {
+                        samples: samples.len(),
+                        frames: held_frames,
+                    });
+                    held_frames = 0.0;
+                }
+            }
+            Ok(Decoded::Flush(seek)) => {
+                let time = seek.target.time();
+                samples.clear();
+                offset = 0;
+                chunk = None;
+                held_frames = 0.0;
+                stretch.reset();
+                effects.lock().unwrap().reset();
+                skip_until = Some(time);
+                let frames = time.as_nanos() * output.info.rate as u128 / 1_000_000_000;
+                shared
+                    .flush_position
+                    .store(frames as u64, Ordering::Relaxed);
+                shared.flush.store(true, Ordering::Release);
+                shared.eof.store(false, Ordering::Relaxed);
+                shared.ended.store(false, Ordering::Relaxed);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(Decoded::End) | Err(RecvTimeoutError::Disconnected) => {
+                shared.eof.store(true, Ordering::Relaxed);
+                shared.starved.store(false, Ordering::Relaxed);
+                if buffering {
+                    buffering = false;
+                    events.send(AudioEvent::BufferingEnd).ok();
+                }
+            }
+        }
+    }
+}
+
+/// Number of interleaved samples of an audio frame before `time`.
+fn samples_before(frame: &ArcFrame, time: Duration) -> usize {
+    let info = match &frame.kind {
+        MediaKind::Audio(info) => info,
+        _ => return 0,
+    };
+    match timestamp(frame.t.pts, frame.t.timebase) {
+        Some(pts) if pts < time => {
+            let frames = (time - pts).as_nanos() * info.rate as u128 / 1_000_000_000;
+            frames as usize * info.map.len()
+        }
+        _ => 0,
+    }
+}
+
+/// Device callback state, pulls samples from the ring buffer without blocking.
+struct Renderer {
+    consumer: Consumer<f32>,
+    chunks: Consumer<Chunk>,
+    /// Part of the chunk the next samples belong to that wasn't played yet.
+    chunk: Chunk,
+    /// Receives a copy of the played samples while metering is enabled.
+    analysis: Producer<f32>,
+    shared: Arc<Shared>,
+    channels: usize,
+    rate: usize,
+    /// Volume and mute.
+    gain: Ramp,
+    /// Balance of the left and right channels.
+    left: Ramp,
+    right: Ramp,
+    /// Fade in and out on play and pause.
+    fade: Ramp,
+    /// Fade out before and in after a flush.
+    flush_fade: Ramp,
+    /// Set after a flush until the first samples following it are read.
+    flushed: bool,
+    /// Source frames played, fractional at playback speeds other than 1.
+    played: f64,
+    buffer: Vec<f32>,
+}
+
+impl Renderer {
+    fn new(
+        consumer: Consumer<f32>,
+        chunks: Consumer<Chunk>,
+        analysis: Producer<f32>,
+        shared: Arc<Shared>,
+        channels: usize,
+        rate: usize,
+    ) -> Self {
+        Self {
+            consumer,
+            chunks,
+            chunk: Chunk::default(),
+            analysis,
+            shared,
+            channels: channels.max(1),
+            rate,
+            gain: Ramp::new(1.0),
+            left: Ramp::new(1.0),
+            right: Ramp::new(1.0),
+            fade: Ramp::new(0.0),
+            flush_fade: Ramp::new(1.0),
+            flushed: false,
+            played: 0.0,
+            buffer: vec![0.0; RENDER_FRAMES * channels.max(1)],
+        }
+    }
+
+    fn render<T: Sample>(&mut self, out: &mut [T]) {
+        let len = self.buffer.len();
+        for part in out.chunks_mut(len) {
+            self.render_part(part);
+        }
+    }
+
+    /// Renders at most `self.buffer.len()` samples.
+    fn render_part<T: Sample>(&mut self, out: &mut [T]) {
+        self.update_flush();
+        let mut gain = self.shared.volume.load();
+        if self.shared.ducked.load(Ordering::Relaxed) {
+            gain *= DUCK_GAIN;
+        }
+        if self.shared.muted.load(Ordering::Relaxed) {
+            gain = 0.0;
+        }
+        let ramp_frames = self.frames(self.shared.ramp_duration.load(Ordering::Relaxed));
+        self.gain.set_target(gain as f32, ramp_frames);
+        let (left, right) = balance_gains(self.shared.balance.load());
+        self.left.set_target(left, ramp_frames);
+        self.right.set_target(right, ramp_frames);
+        let fade = if self.shared.active.load(Ordering::Relaxed) {
+            1.0
+        } else {
+            0.0
+        };
+        self.fade.set_target(fade, self.frames(FADE_DURATION_MS));
+
+        let buffer = &mut self.buffer[..out.len()];
+        let read = self.consumer.pop_slice(buffer);
+        if read > 0 && self.flushed {
+            self.flushed = false;
+            self.flush_fade = Ramp::new(0.0);
+            let fade_frames = self.frames(FLUSH_FADE_DURATION_MS);
+            self.flush_fade.set_target(1.0, fade_frames);
+        }
+        self.advance_clock(read);
+        self.shared
+            .played
+            .store(self.played as u64, Ordering::Relaxed);
+        if read < buffer.len() {
+            for sample in &mut buffer[read..] {
+                *sample = 0.0;
+            }
+            if !self.shared.eof.load(Ordering::Relaxed) {
+                let silent = (buffer.len() - read) as u64;
+                self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+                self.shared
+                    .silent_samples
+                    .fetch_add(silent, Ordering::Relaxed);
+                self.shared.starved.store(true, Ordering::Relaxed);
+            }
+        } else {
+            self.shared.starved.store(false, Ordering::Relaxed);
+        }
+
+        for (out, frame) in out
+            .chunks_mut(self.channels)
+            .zip(buffer.chunks_mut(self.channels))
+        {
+            let gain = self.gain.next() * self.fade.next() * self.flush_fade.next();
+            let (left, right) = (self.left.next(), self.right.next());
+            for (ch, (out, sample)) in out.iter_mut().zip(frame).enumerate() {
+                let pan = match (self.channels, ch) {
+                    (1, _) => 1.0,
+                    (_, 0) => left,
+                    (_, 1) => right,
+                    _ => 1.0,
+                };
+                *sample = (*sample * gain * pan).clamp(-1.0, 1.0);
+                *out = T::from(sample);
+            }
+        }
+        // Samples that don't fit are dropped, metering only needs a recent
+        // excerpt.
+        if self.shared.metering.load(Ordering::Relaxed) {
+            self.analysis.push_slice(buffer);
+        }
+        let silent = self.fade.is_settled() && self.fade.value() == 0.0;
+        self.shared.silent.store(silent, Ordering::Relaxed);
+    }
+
+    /// Renders only the samples already buffered, for sinks that consume
+    /// them faster than real time. Returns the number of samples written to
+    /// the start of `out`.
+    fn render_buffered(&mut self, out: &mut [f32]) -> usize {
+        self.update_flush();
+        let len = self.consumer.len().min(out.len()) / self.channels * self.channels;
+        self.render(&mut out[..len]);
+        len
+    }
+
+    /// Handles a flush the worker asked for, the samples buffered before the
+    /// seek are faded out first unless nothing is audible.
+    fn update_flush(&mut self) {
+        if !self.shared.flush.load(Ordering::Acquire) {
+            return;
+        }
+        let silent = self.fade.is_settled() && self.fade.value() == 0.0;
+        let faded_out = self.flush_fade.is_settled() && self.flush_fade.value() == 0.0;
+        if silent {
+            self.apply_flush();
+            self.flush_fade = Ramp::new(1.0);
+            self.flushed = false;
+        } else if faded_out || self.consumer.len() == 0 {
+            self.apply_flush();
+            self.flushed = true;
+        } else {
+            let fade_frames = self.frames(FLUSH_FADE_DURATION_MS);
+            self.flush_fade.set_target(0.0, fade_frames);
+        }
+    }
+
+    /// Discards the samples buffered before a seek once the worker asks for
+    /// it.
+    fn apply_flush(&mut self) {
+        if self.shared.flush.load(Ordering::Acquire) {
+            while self.consumer.pop_slice(&mut self.buffer) > 0 {}
+            while self.chunks.pop_slice(&mut [Chunk::default()]) > 0 {}
+            self.chunk = Chunk::default();
+            self.played = self.shared.flush_position.load(Ordering::Relaxed) as f64;
+            self.shared.flush.store(false, Ordering::Release);
+        }
+    }
+
+    /// Credits the source frames `samples` played samples were stretched
+    /// from.
+    fn advance_clock(&mut self, mut samples: usize) {
+        while samples > 0 {
+            if self.chunk.samples == 0 {
+                let mut next = [Chunk::default()];
+                if self.chunks.pop_slice(&mut next) == 0 {
+                    break;
+                }
+                self.chunk = next[0];
+                continue;
+            }
+            let played = samples.min(self.chunk.samples);
+            let frames = self.chunk.frames * played as f64 / self.chunk.samples as f64;
+            self.played += frames;
+            self.chunk.frames -= frames;
+            self.chunk.samples -= played;
+            samples -= played;
+        }
+    }
+
+    /// Number of frames played in `millis` milliseconds.
+    fn frames(&self, millis: u64) -> usize {
+        self.rate * millis as usize / 1000
+    }
+}
+
+/// Converts the samples of a decoded audio frame to interleaved `f32` in the
+/// range `[-1.0, 1.0]`, replacing the contents of `out`.
+pub fn read_samples(frame: &ArcFrame, out: &mut Vec<f32>) {
+    out.clear();
+    let info = match &frame.kind {
+        MediaKind::Audio(info) => info,
+        _ => return,
+    };
+    match (info.format.float, info.format.signed, info.format.bits) {
+        (true, _, 32) => push_samples(info, out, |idx| frame.buf.as_slice(idx).ok(), |s: f32| s),
+        (false, true, 16) => push_samples(
+            info,
+            out,
+            |idx| frame.buf.as_slice(idx).ok(),
+            |s: i16| s as f32 / 32_768.0,
+        ),
+        (false, false, 8) => push_samples(
+            info,
+            out,
+            |idx| frame.buf.as_slice(idx).ok(),
+            |s: u8| (s as f32 - 128.0) / 128.0,
+        ),
+        _ => log::warn!("unsupported sample format {:?}", info.format),
+    }
+}
+
+fn push_samples<'a, T: Copy + 'a>(
+    info: &FrameAudioInfo,
+    out: &mut Vec<f32>,
+    plane: impl Fn(usize) -> Option<&'a [T]>,
+    convert: impl Fn(T) -> f32,
+) {
+    let channels = info.map.len();
+    out.reserve(info.samples * channels);
+    if info.format.planar {
+        let planes: Vec<&[T]> = (0..channels).filter_map(&plane).collect();
+        if planes.len() != channels {
+            return;
+        }
+        for i in 0..info.samples {
+            for plane in &planes {
+                out.push(convert(plane[i]));
+            }
+        }
+    } else if let Some(data) = plane(0) {
+        let len = data.len().min(info.samples * channels);
+        out.extend(data[..len].iter().map(|s| convert(*s)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renderer(rate: usize) -> (Renderer, Producer<f32>, Producer<Chunk>, Arc<Shared>) {
+        let (producer, consumer) = ring_buffer(rate * 4);
+        let (chunks, chunk_consumer) = ring_buffer(16);
+        let (analysis, _) = ring_buffer(16);
+        let shared = Arc::new(Shared::new());
+        let renderer = Renderer::new(consumer, chunk_consumer, analysis, shared.clone(), 1, rate);
+        (renderer, producer, chunks, shared)
+    }
+
+    #[test]
+    fn clock_counts_source_frames() {
+        let (mut renderer, mut producer, mut chunks, shared) = renderer(1000);
+        // A second at normal speed, then a second stretched to a quarter.
+        chunks.push_slice(&[
+            Chunk {
+                samples: 1000,
+                frames: 1000.0,
+            },
+            Chunk {
+                samples: 250,
+                frames: 1000.0,
+            },
+        ]);
+        producer.push_slice(&[0.0; 1250]);
+        let mut out = [0.0f32; 500];
+        renderer.render(&mut out);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 500);
+        renderer.render(&mut out);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 1000);
+        renderer.render(&mut out[..125]);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 1500);
+        renderer.render(&mut out[..125]);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 2000);
+    }
+
+    #[test]
+    fn renders_large_requests_in_parts() {
+        let (mut renderer, mut producer, mut chunks, shared) = renderer(48_000);
+        let len = RENDER_FRAMES * 2 + 100;
+        chunks.push_slice(&[Chunk {
+            samples: len,
+            frames: len as f64,
+        }]);
+        producer.push_slice(&vec![0.0; len]);
+        let capacity = renderer.buffer.capacity();
+        let mut out = vec![0.0f32; len];
+        renderer.render(&mut out);
+        assert_eq!(renderer.buffer.capacity(), capacity);
+        assert_eq!(shared.played.load(Ordering::Relaxed), len as u64);
+        assert!(!shared.starved.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn speed_change_keeps_clock_of_buffered_samples() {
+        let (mut renderer, mut producer, mut chunks, shared) = renderer(1000);
+        chunks.push_slice(&[Chunk {
+            samples: 500,
+            frames: 500.0,
+        }]);
+        producer.push_slice(&[0.0; 500]);
+        // Samples stretched at the old speed are still buffered.
+        shared.speed.store(4.0);
+        let mut out = [0.0f32; 500];
+        renderer.render(&mut out);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 500);
+    }
+
+    #[test]
+    fn flush_discards_chunks() {
+        let (mut renderer, mut producer, mut chunks, shared) = renderer(1000);
+        chunks.push_slice(&[Chunk {
+            samples: 500,
+            frames: 2000.0,
+        }]);
+        producer.push_slice(&[0.0; 500]);
+        shared.flush_position.store(3000, Ordering::Relaxed);
+        shared.flush.store(true, Ordering::Release);
+        renderer.apply_flush();
+        // The worker continues after the flush was acknowledged.
+        chunks.push_slice(&[Chunk {
+            samples: 100,
+            frames: 100.0,
+        }]);
+        producer.push_slice(&[0.0; 100]);
+        let mut out = [0.0f32; 100];
+        renderer.render(&mut out);
+        assert_eq!(shared.played.load(Ordering::Relaxed), 3100);
+    }
+
+    #[test]
+    fn flush_fades_out_and_in() {
+        let (mut renderer, mut producer, _chunks, shared) = renderer(1000);
+        shared.active.store(true, Ordering::Relaxed);
+        producer.push_slice(&[1.0; 100]);
+        let mut out = [0.0f32; 100];
+        renderer.render(&mut out);
+        assert_eq!(out[99], 1.0);
+
+        producer.push_slice(&[1.0; 100]);
+        shared.flush.store(true, Ordering::Release);
+        let mut before = [0.0f32; 10];
+        renderer.render(&mut before);
+        // Still fading out the samples from before the seek.
+        assert!(shared.flush.load(Ordering::Acquire));
+        assert_ramp(&before, &[0.8, 0.6, 0.4, 0.2, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        // The rest is discarded, the worker continues once it sees that.
+        renderer.render(&mut before);
+        assert!(!shared.flush.load(Ordering::Acquire));
+        assert_eq!(renderer.consumer.len(), 0);
+        producer.push_slice(&[1.0; 10]);
+        let mut after = [0.0f32; 10];
+        renderer.render(&mut after);
+        assert_ramp(&after, &[0.2, 0.4, 0.6, 0.8, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    fn assert_ramp(actual: &[f32], expected: &[f32]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-4,
+                "{:?} != {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn flush_while_paused_is_immediate() {
+        let (mut renderer, mut producer, _chunks, shared) = renderer(1000);
+        producer.push_slice(&[1.0; 100]);
+        shared.flush.store(true, Ordering::Release);
+        let mut out = [0.0f32; 10];
+        renderer.render(&mut out);
+        assert!(!shared.flush.load(Ordering::Acquire));
+        assert_eq!(renderer.consumer.len(), 0);
     }
 }
@@ -0,0 +1,283 @@
+//! Audio filters.
+use serde::{Deserialize, Serialize};
+
+/// Second order IIR filter in transposed direct form II.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Creates a filter from coefficients normalized to `a0 = 1`.
+    pub fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    /// Clears the filter state.
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+/// An effect in the audio chain between the decoder and the output device.
+///
+/// Effects run on the audio worker thread, not the device callback, so they
+/// may allocate and lock, but should keep up with real time.
+pub trait AudioEffect: Send {
+    /// Called with the stream format before the first call to `process`.
+    fn configure(&mut self, channels: usize, rate: usize);
+
+    /// Processes interleaved samples in place.
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// Clears any state carried over from previous samples, e.g. after a
+    /// seek.
+    fn reset(&mut self) {}
+}
+
+/// Runs the equalizer, effects registered by the application and finally
+/// the limiter.
+pub struct EffectChain {
+    equalizer: Equalizer,
+    effects: Vec<Box<dyn AudioEffect>>,
+    limiter: Limiter,
+}
+
+impl EffectChain {
+    pub fn new(mut effects: Vec<Box<dyn AudioEffect>>, channels: usize, rate: usize) -> Self {
+        let mut equalizer = Equalizer::new(Vec::new());
+        let mut limiter = Limiter::new(LIMITER_THRESHOLD);
+        equalizer.configure(channels, rate);
+        for effect in &mut effects {
+            effect.configure(channels, rate);
+        }
+        limiter.configure(channels, rate);
+        Self {
+            equalizer,
+            effects,
+            limiter,
+        }
+    }
+
+    pub fn set_equalizer_bands(&mut self, bands: Vec<EqualizerBand>) {
+        self.equalizer.set_bands(bands);
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        self.equalizer.process(samples);
+        for effect in &mut self.effects {
+            effect.process(samples);
+        }
+        self.limiter.process(samples);
+    }
+
+    /// Clears the state of every effect, e.g. after a seek.
+    pub fn reset(&mut self) {
+        self.equalizer.reset();
+        for effect in &mut self.effects {
+            effect.reset();
+        }
+        self.limiter.reset();
+    }
+}
+
+/// Shape of an equalizer band.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BandKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+/// An equalizer band, see the Audio EQ Cookbook for the filter definitions.
+#[derive(Clone, Copy, Debug)]
+pub struct EqualizerBand {
+    pub kind: BandKind,
+    /// Center or corner frequency in Hz.
+    pub frequency: f64,
+    /// Gain in dB.
+    pub gain: f64,
+    pub q: f64,
+}
+
+impl EqualizerBand {
+    fn filter(&self, rate: usize) -> Biquad {
+        let nyquist = rate as f64 / 2.0;
+        let frequency = self.frequency.clamp(10.0, nyquist * 0.98);
+        let q = self.q.max(0.05);
+        let a = 10f64.powf(self.gain / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency / rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let (b0, b1, b2, a0, a1, a2) = match self.kind {
+            BandKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            BandKind::LowShelf => {
+                let beta = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + beta),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - beta),
+                    (a + 1.0) + (a - 1.0) * cos + beta,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - beta,
+                )
+            }
+            BandKind::HighShelf => {
+                let beta = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - beta),
+                    (a + 1.0) - (a - 1.0) * cos + beta,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - beta,
+                )
+            }
+        };
+        Biquad::new(b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0)
+    }
+}
+
+/// Multi-band equalizer built from one biquad per band and channel.
+pub struct Equalizer {
+    bands: Vec<EqualizerBand>,
+    channels: usize,
+    rate: usize,
+    /// Filters indexed by band, then channel.
+    filters: Vec<Vec<Biquad>>,
+}
+
+impl Equalizer {
+    pub fn new(bands: Vec<EqualizerBand>) -> Self {
+        Self {
+            bands,
+            channels: 0,
+            rate: 0,
+            filters: Vec::new(),
+        }
+    }
+
+    /// Replaces the bands, the filter state of the new bands starts out
+    /// empty.
+    pub fn set_bands(&mut self, bands: Vec<EqualizerBand>) {
+        self.bands = bands;
+        self.update_filters();
+    }
+
+    fn update_filters(&mut self) {
+        let (channels, rate) = (self.channels, self.rate);
+        self.filters = self
+            .bands
+            .iter()
+            .map(|band| vec![band.filter(rate); channels])
+            .collect();
+    }
+}
+
+impl AudioEffect for Equalizer {
+    fn configure(&mut self, channels: usize, rate: usize) {
+        self.channels = channels.max(1);
+        self.rate = rate;
+        self.update_filters();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for band in &mut self.filters {
+            for frame in samples.chunks_exact_mut(self.channels) {
+                for (sample, filter) in frame.iter_mut().zip(band.iter_mut()) {
+                    *sample = filter.process(f64::from(*sample)) as f32;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+    }
+}
+
+/// Peak level the limiter keeps the output below, -1 dBFS.
+const LIMITER_THRESHOLD: f32 = 0.891;
+/// Time for the limiter gain to recover after a peak.
+const LIMITER_RELEASE_MS: f32 = 100.0;
+
+/// Peak limiter with instant attack, keeps boosts from the equalizer or
+/// loudness normalization from clipping.
+pub struct Limiter {
+    threshold: f32,
+    channels: usize,
+    /// Per frame factor the gain recovers by.
+    release: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            channels: 1,
+            release: 0.0,
+            gain: 1.0,
+        }
+    }
+}
+
+impl AudioEffect for Limiter {
+    fn configure(&mut self, channels: usize, rate: usize) {
+        self.channels = channels.max(1);
+        let release_frames = LIMITER_RELEASE_MS / 1000.0 * rate as f32;
+        self.release = (-1.0 / release_frames.max(1.0)).exp();
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            let limit = if peak > self.threshold {
+                self.threshold / peak
+            } else {
+                1.0
+            };
+            // Recover exponentially towards unity, but never above the limit.
+            self.gain = (1.0 - (1.0 - self.gain) * self.release).min(limit);
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.gain = 1.0;
+    }
+}
@@ -0,0 +1,41 @@
+//! Events published by a `Player`.
+use crate::meter::AudioLevels;
+use crate::player::PlayerState;
+use std::time::Duration;
+
+/// Something that happened during playback, see `Player::events`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlayerEvent {
+    /// Sent once the first video frame was presented after the player was
+    /// created, see `PlayerState::Initializing`.
+    Initialized {
+        width: i64,
+        height: i64,
+        /// `None` if the container doesn't know its duration.
+        duration: Option<Duration>,
+    },
+    StateChanged(PlayerState),
+    /// Playback position, sent periodically while playing.
+    Position(Duration),
+    /// Whether playback is stalled waiting for decoded audio.
+    Buffering(bool),
+    /// Decoding failed and stopped.
+    Error(String),
+    /// Every stream played to its end.
+    Completed,
+    /// The streams being played changed, given as stream indices of the
+    /// container.
+    TracksChanged {
+        video: Option<usize>,
+        audio: Option<usize>,
+    },
+    /// The size of the decoded video changed.
+    SizeChanged {
+        width: i64,
+        height: i64,
+    },
+    /// Audio moved to the named output device.
+    AudioDeviceChanged(String),
+    /// Levels of the played audio, sent while metering is enabled.
+    AudioLevels(AudioLevels),
+}
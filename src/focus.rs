@@ -0,0 +1,75 @@
+//! Audio focus between players that play at the same time.
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Volume factor applied to players while another player ducks them.
+pub const DUCK_GAIN: f64 = 0.2;
+
+/// How a player treats the other players when it starts playing.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FocusPolicy {
+    /// Pause all other players.
+    Exclusive,
+    /// Play along with the other players.
+    Mix,
+    /// Lower the volume of the other players while this one plays.
+    DuckOthers,
+}
+
+impl Default for FocusPolicy {
+    fn default() -> Self {
+        Self::Mix
+    }
+}
+
+/// Tracks the focus policy of each player and which players are ducking.
+#[derive(Default)]
+pub struct AudioFocus {
+    default: FocusPolicy,
+    policies: HashMap<i64, FocusPolicy>,
+    duckers: HashSet<i64>,
+}
+
+impl AudioFocus {
+    /// Sets the policy of players without one of their own.
+    pub fn set_default(&mut self, policy: FocusPolicy) {
+        self.default = policy;
+    }
+
+    pub fn set_policy(&mut self, id: i64, policy: FocusPolicy) {
+        self.policies.insert(id, policy);
+    }
+
+    pub fn policy(&self, id: i64) -> FocusPolicy {
+        self.policies.get(&id).copied().unwrap_or(self.default)
+    }
+
+    /// Records that `id` started playing and returns its policy, which tells
+    /// the caller what to do with the other players.
+    pub fn on_play(&mut self, id: i64) -> FocusPolicy {
+        let policy = self.policy(id);
+        if policy == FocusPolicy::DuckOthers {
+            self.duckers.insert(id);
+        } else {
+            self.duckers.remove(&id);
+        }
+        policy
+    }
+
+    /// Records that `id` stopped playing.
+    pub fn on_stop(&mut self, id: i64) {
+        self.duckers.remove(&id);
+    }
+
+    /// Forgets a disposed player.
+    pub fn remove(&mut self, id: i64) {
+        self.duckers.remove(&id);
+        self.policies.remove(&id);
+    }
+
+    /// Whether `id` is currently ducked by another player.
+    pub fn is_ducked(&self, id: i64) -> bool {
+        self.duckers.iter().any(|ducker| *ducker != id)
+    }
+}
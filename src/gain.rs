@@ -0,0 +1,70 @@
+//! Click free gain changes.
+
+/// A gain that moves linearly towards its target over a number of frames.
+pub struct Ramp {
+    value: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+}
+
+impl Ramp {
+    pub fn new(value: f32) -> Self {
+        Self {
+            value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Starts moving towards `target`, reaching it after `frames` calls to
+    /// `next`. Does nothing if `target` is already the target.
+    pub fn set_target(&mut self, target: f32, frames: usize) {
+        if (target - self.target).abs() < f32::EPSILON {
+            return;
+        }
+        self.target = target;
+        if frames == 0 {
+            self.value = target;
+            self.remaining = 0;
+        } else {
+            self.step = (target - self.value) / frames as f32;
+            self.remaining = frames;
+        }
+    }
+
+    /// Advances the ramp by one frame and returns the gain for that frame.
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 {
+                self.target
+            } else {
+                self.value + self.step
+            };
+        }
+        self.value
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Whether the target has been reached.
+    pub fn is_settled(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// Left and right channel gains for a balance between `-1.0` (left) and
+/// `1.0` (right).
+///
+/// Uses an equal-power pan law scaled so that both channels are at unity in
+/// the center, moving towards one side only attenuates the other.
+pub fn balance_gains(balance: f32) -> (f32, f32) {
+    let angle = (balance.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    let left = angle.cos() * std::f32::consts::SQRT_2;
+    let right = angle.sin() * std::f32::consts::SQRT_2;
+    (left.min(1.0), right.min(1.0))
+}
@@ -1,7 +1,31 @@
 mod audio;
+mod dsp;
+mod event;
+mod focus;
+mod gain;
+mod loudness;
+mod metadata;
+mod meter;
 mod player;
 mod plugin;
+mod queue;
+mod ring;
+mod sink;
+mod snapshot;
+mod stretch;
 mod types;
 mod video;
 
+pub use audio::{output_devices, AudioDevice, AudioError, AudioEvent, AudioStats};
+pub use dsp::{AudioEffect, BandKind, EqualizerBand};
+pub use event::PlayerEvent;
+pub use metadata::{Attachment, Metadata, TrackKind, TrackMetadata};
+pub use meter::AudioLevels;
+pub use player::{
+    PlaybackContext, Player, PlayerBuilder, PlayerError, PlayerState, MAX_SPEED, MIN_SPEED,
+};
 pub use plugin::VideoPlugin;
+pub use queue::ReadAhead;
+pub use sink::{AudioSink, InvalidAudioSink};
+pub use snapshot::{thumbnails, ImageFormat, VideoFrame};
+pub use video::{FrameSink, MemoryFrameSink, PostedFrame};
@@ -0,0 +1,233 @@
+//! Loudness normalization using ReplayGain / R128 tags or an EBU R128
+//! measurement of the integrated loudness.
+use crate::dsp::Biquad;
+use crate::metadata::Tag;
+
+/// Loudness that normalized playback is adjusted to, the ReplayGain 2.0
+/// reference level.
+pub const TARGET_LOUDNESS: f64 = -18.0;
+
+/// Reference level of `R128_*_GAIN` tags.
+const R128_REFERENCE: f64 = -23.0;
+
+/// Normalization never amplifies by more than this to avoid clipping quiet
+/// tracks with loud peaks.
+const MAX_GAIN_DB: f64 = 12.0;
+
+/// Length of a gating block.
+const BLOCK_MS: usize = 400;
+/// Distance between gating blocks, blocks overlap by 75%.
+const STEP_MS: usize = 100;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Returns the normalization gain in dB from ReplayGain or R128 track gain
+/// tags, if present.
+///
+/// Tags targeting the track with `track_uid` are preferred over tags of the
+/// whole file, tags of other tracks are ignored.
+pub fn gain_from_tags(tags: &[Tag], track_uid: Option<u64>) -> Option<f64> {
+    let find = |name: &str| {
+        let tagged = |target: Option<u64>| {
+            tags.iter()
+                .find(|tag| tag.track_uid == target && tag.name.eq_ignore_ascii_case(name))
+        };
+        track_uid
+            .and_then(|uid| tagged(Some(uid)))
+            .or_else(|| tagged(None))
+            .map(|tag| tag.value.trim())
+    };
+    if let Some(value) = find("R128_TRACK_GAIN") {
+        // Q7.8 fixed point relative to -23 LUFS.
+        if let Ok(gain) = value.parse::<i32>() {
+            let gain = f64::from(gain) / 256.0 + TARGET_LOUDNESS - R128_REFERENCE;
+            return Some(limit_gain(gain));
+        }
+    }
+    if let Some(value) = find("REPLAYGAIN_TRACK_GAIN") {
+        let value = value.trim_end_matches("dB").trim_end_matches("db").trim();
+        if let Ok(gain) = value.parse::<f64>() {
+            return Some(limit_gain(gain));
+        }
+    }
+    None
+}
+
+/// Returns the normalization gain in dB for a measured integrated loudness.
+pub fn gain_from_loudness(loudness: f64) -> f64 {
+    limit_gain(TARGET_LOUDNESS - loudness)
+}
+
+fn limit_gain(gain: f64) -> f64 {
+    gain.min(MAX_GAIN_DB)
+}
+
+/// Measures integrated loudness as specified in ITU-R BS.1770 / EBU R128.
+pub struct LoudnessMeter {
+    channels: usize,
+    /// K-weighting filters, a shelf and a high pass per channel.
+    filters: Vec<(Biquad, Biquad)>,
+    step_len: usize,
+    step_pos: usize,
+    /// Weighted energy of the current step.
+    step_energy: f64,
+    /// Mean energy of the last steps, a block is the last four.
+    steps: Vec<f64>,
+    /// Mean energy of each complete block.
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, rate: usize) -> Self {
+        let channels = channels.max(1);
+        let filters = (0..channels)
+            .map(|_| (shelf_filter(rate), high_pass_filter(rate)))
+            .collect();
+        Self {
+            channels,
+            filters,
+            step_len: (rate * STEP_MS / 1000).max(1),
+            step_pos: 0,
+            step_energy: 0.0,
+            steps: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Adds interleaved samples to the measurement.
+    pub fn process(&mut self, samples: &[f32]) {
+        let weights: Vec<f64> = (0..self.channels)
+            .map(|ch| channel_weight(self.channels, ch))
+            .collect();
+        for frame in samples.chunks_exact(self.channels) {
+            for (ch, sample) in frame.iter().enumerate() {
+                let (shelf, high_pass) = &mut self.filters[ch];
+                let filtered = high_pass.process(shelf.process(f64::from(*sample)));
+                self.step_energy += weights[ch] * filtered * filtered;
+            }
+            self.step_pos += 1;
+            if self.step_pos == self.step_len {
+                self.finish_step();
+            }
+        }
+    }
+
+    fn finish_step(&mut self) {
+        self.steps.push(self.step_energy / self.step_len as f64);
+        self.step_energy = 0.0;
+        self.step_pos = 0;
+        let steps_per_block = BLOCK_MS / STEP_MS;
+        if self.steps.len() >= steps_per_block {
+            let block = &self.steps[self.steps.len() - steps_per_block..];
+            self.blocks
+                .push(block.iter().sum::<f64>() / steps_per_block as f64);
+            self.steps.remove(0);
+        }
+    }
+
+    /// Gated integrated loudness in LUFS, `None` if everything was silent.
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|energy| loudness(*energy) > ABSOLUTE_GATE)
+            .collect();
+        if absolute.is_empty() {
+            return None;
+        }
+        let relative_gate = loudness(mean(&absolute)) + RELATIVE_GATE;
+        let relative: Vec<f64> = absolute
+            .into_iter()
+            .filter(|energy| loudness(*energy) > relative_gate)
+            .collect();
+        if relative.is_empty() {
+            return None;
+        }
+        Some(loudness(mean(&relative)))
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.max(std::f64::MIN_POSITIVE).log10()
+}
+
+/// Channel weights for the usual L, R, C, LFE, Ls, Rs order, the LFE channel
+/// isn't counted and surround channels are boosted.
+fn channel_weight(channels: usize, ch: usize) -> f64 {
+    match (channels, ch) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// First stage of the K-weighting, models the acoustic effect of the head.
+fn shelf_filter(rate: usize) -> Biquad {
+    let f0 = 1681.974_450_955_533;
+    let gain = 3.999_843_853_973_347;
+    let q = 0.707_175_236_955_419_6;
+    let k = (std::f64::consts::PI * f0 / rate as f64).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    Biquad::new(
+        (vh + vb * k / q + k * k) / a0,
+        2.0 * (k * k - vh) / a0,
+        (vh - vb * k / q + k * k) / a0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    )
+}
+
+/// Second stage of the K-weighting, the RLB high pass.
+fn high_pass_filter(rate: usize) -> Biquad {
+    let f0 = 38.135_470_876_024_44;
+    let q = 0.500_327_037_323_877_3;
+    let k = (std::f64::consts::PI * f0 / rate as f64).tan();
+    let a0 = 1.0 + k / q + k * k;
+    Biquad::new(
+        1.0,
+        -2.0,
+        1.0,
+        2.0 * (k * k - 1.0) / a0,
+        (1.0 - k / q + k * k) / a0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(track_uid: Option<u64>, name: &str, value: &str) -> Tag {
+        Tag {
+            track_uid,
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn prefers_tags_of_the_track() {
+        let tags = [
+            tag(Some(1), "REPLAYGAIN_TRACK_GAIN", "-1 dB"),
+            tag(None, "REPLAYGAIN_TRACK_GAIN", "-2 dB"),
+            tag(Some(2), "REPLAYGAIN_TRACK_GAIN", "-3 dB"),
+        ];
+        assert_eq!(gain_from_tags(&tags, Some(2)), Some(-3.0));
+        assert_eq!(gain_from_tags(&tags, Some(3)), Some(-2.0));
+        assert_eq!(gain_from_tags(&tags, None), Some(-2.0));
+    }
+
+    #[test]
+    fn ignores_tags_of_other_tracks() {
+        let tags = [tag(Some(1), "R128_TRACK_GAIN", "-512")];
+        assert_eq!(gain_from_tags(&tags, Some(2)), None);
+        assert_eq!(gain_from_tags(&tags, None), None);
+        assert_eq!(gain_from_tags(&tags, Some(1)), Some(3.0));
+    }
+}
@@ -1,4 +1,4 @@
-use flutter_video_plugin::VideoPlugin;
+use flutter_video_plugin::{AudioSink, VideoPlugin};
 use flutter_winit::FlutterWindow;
 use glutin::window::WindowBuilder;
 use std::path::{Path, PathBuf};
@@ -25,7 +25,14 @@ fn main() {
     let flutter = FlutterWindow::new(window, PathBuf::from(assets_dir)).unwrap();
     let flutter = flutter.with_resource_context().unwrap();
 
-    flutter.add_plugin(VideoPlugin::default());
+    let mut video_plugin = VideoPlugin::default();
+    if let Ok(sink) = std::env::var("VIDEO_AUDIO_SINK") {
+        match sink.parse::<AudioSink>() {
+            Ok(sink) => video_plugin = video_plugin.with_audio_sink(sink),
+            Err(err) => log::warn!("ignoring VIDEO_AUDIO_SINK: {}", err),
+        }
+    }
+    flutter.add_plugin(video_plugin);
 
     flutter.start_engine(&args).unwrap();
 
@@ -0,0 +1,1042 @@
+//! Reads metadata elements from Matroska / WebM files.
+//!
+//! The demuxer only exposes what is needed for decoding, so this walks the
+//! EBML structure of the file directly, skipping over clusters.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const CLUSTER: u32 = 0x1F43_B675;
+const INFO: u32 = 0x1549_A966;
+const TITLE: u32 = 0x7BA9;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DATE_UTC: u32 = 0x4461;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const NAME: u32 = 0x536E;
+const LANGUAGE: u32 = 0x22_B59C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_NAME: u32 = 0x25_8688;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const ATTACHMENTS: u32 = 0x1941_A469;
+const ATTACHED_FILE: u32 = 0x61A7;
+const FILE_NAME: u32 = 0x466E;
+const FILE_MIME_TYPE: u32 = 0x4660;
+const FILE_DATA: u32 = 0x465C;
+const FILE_DESCRIPTION: u32 = 0x467E;
+const TAGS: u32 = 0x1254_C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const TAG_TRACK_UID: u32 = 0x63C5;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const REFERENCE_BLOCK: u32 = 0xFB;
+
+/// Elements larger than this are skipped instead of read into memory.
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+/// Timestamps are in nanoseconds unless the file says otherwise.
+const DEFAULT_TIMECODE_SCALE: u64 = 1_000_000;
+/// `DateUTC` counts from 2001-01-01T00:00:00 UTC.
+const DATE_EPOCH: Duration = Duration::from_secs(978_307_200);
+
+/// A simple tag, e.g. `TITLE` or `REPLAYGAIN_TRACK_GAIN`.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    /// Track the tag applies to, `None` for the whole file.
+    pub track_uid: Option<u64>,
+    pub name: String,
+    pub value: String,
+}
+
+/// Container and track metadata of a Matroska file.
+#[derive(Clone, Debug, Default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+    /// Average bits per second of the whole file.
+    pub bitrate: Option<u64>,
+    /// When the file was created.
+    pub date: Option<SystemTime>,
+    pub muxing_app: Option<String>,
+    pub writing_app: Option<String>,
+    /// Values of the tags of the whole file by name, in file order.
+    pub tags: HashMap<String, Vec<String>>,
+    pub tracks: Vec<TrackMetadata>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// Kind of a Matroska track.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+impl TrackKind {
+    fn from_type(track_type: u64) -> Self {
+        match track_type {
+            1 => Self::Video,
+            2 => Self::Audio,
+            0x11 => Self::Subtitle,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Metadata of a track.
+#[derive(Clone, Debug)]
+pub struct TrackMetadata {
+    /// Number blocks refer to the track by, starts at 1.
+    pub number: u64,
+    pub uid: Option<u64>,
+    pub kind: TrackKind,
+    pub name: Option<String>,
+    /// ISO 639-2 language code.
+    pub language: String,
+    /// Matroska codec id, e.g. `V_VP9`.
+    pub codec_id: String,
+    /// Human readable name of the codec.
+    pub codec_name: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    /// Sampling frequency in Hz.
+    pub sample_rate: Option<f64>,
+    pub channels: Option<u64>,
+    /// Values of the tags of the track by name, in file order.
+    pub tags: HashMap<String, Vec<String>>,
+}
+
+/// A file attached to a Matroska file, e.g. cover art.
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub file_name: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Reads the segment info, tracks, tags and attachments of a Matroska file.
+pub fn read_metadata(path: &Path) -> io::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    let mut tags = Vec::new();
+    for (id, data) in read_segment_elements(path, &[INFO, TRACKS, TAGS, ATTACHMENTS])? {
+        match id {
+            INFO => parse_info(&data, &mut metadata),
+            ATTACHED_FILE => metadata.attachments.extend(parse_attachment(&data)),
+            _ => {
+                for (child, body) in Elements::new(&data) {
+                    match (id, child) {
+                        (TRACKS, TRACK_ENTRY) => metadata.tracks.push(parse_track(body)),
+                        (TAGS, TAG) => parse_tag(body, &mut tags),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    for tag in tags {
+        let track = metadata
+            .tracks
+            .iter_mut()
+            .find(|track| track.uid.is_some() && track.uid == tag.track_uid);
+        let tags = match (tag.track_uid, track) {
+            (None, _) => &mut metadata.tags,
+            (Some(_), Some(track)) => &mut track.tags,
+            // Tags of tracks that don't exist.
+            (Some(_), None) => continue,
+        };
+        tags.entry(tag.name).or_default().push(tag.value);
+    }
+    if let Some(duration) = metadata.duration.filter(|d| *d > Duration::from_secs(0)) {
+        let bits = std::fs::metadata(path)?.len() as f64 * 8.0;
+        metadata.bitrate = Some((bits / duration.as_secs_f64()) as u64);
+    }
+    Ok(metadata)
+}
+
+fn parse_info(data: &[u8], metadata: &mut Metadata) {
+    let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+    let mut duration = None;
+    for (id, body) in Elements::new(data) {
+        match id {
+            TITLE => metadata.title = Some(read_string(body)),
+            TIMECODE_SCALE => timecode_scale = read_uint(body),
+            DURATION => duration = read_float(body),
+            MUXING_APP => metadata.muxing_app = Some(read_string(body)),
+            WRITING_APP => metadata.writing_app = Some(read_string(body)),
+            DATE_UTC => {
+                let nanos = read_int(body);
+                let offset = Duration::from_nanos(nanos.wrapping_abs() as u64);
+                metadata.date = if nanos < 0 {
+                    (UNIX_EPOCH + DATE_EPOCH).checked_sub(offset)
+                } else {
+                    (UNIX_EPOCH + DATE_EPOCH).checked_add(offset)
+                };
+            }
+            _ => {}
+        }
+    }
+    // The duration is a float in units of the timecode scale.
+    metadata.duration = duration
+        .map(|duration| duration * timecode_scale as f64)
+        .filter(|nanos| nanos.is_finite() && *nanos >= 0.0)
+        .map(|nanos| Duration::from_nanos(nanos as u64));
+}
+
+fn parse_track(data: &[u8]) -> TrackMetadata {
+    let mut track = TrackMetadata {
+        number: 0,
+        uid: None,
+        kind: TrackKind::Other,
+        name: None,
+        language: "eng".to_string(),
+        codec_id: String::new(),
+        codec_name: None,
+        width: None,
+        height: None,
+        sample_rate: None,
+        channels: None,
+        tags: HashMap::new(),
+    };
+    for (id, body) in Elements::new(data) {
+        match id {
+            TRACK_NUMBER => track.number = read_uint(body),
+            TRACK_UID => track.uid = Some(read_uint(body)),
+            TRACK_TYPE => track.kind = TrackKind::from_type(read_uint(body)),
+            NAME => track.name = Some(read_string(body)),
+            LANGUAGE => track.language = read_string(body),
+            CODEC_ID => track.codec_id = read_string(body),
+            CODEC_NAME => track.codec_name = Some(read_string(body)),
+            VIDEO => {
+                for (id, body) in Elements::new(body) {
+                    match id {
+                        PIXEL_WIDTH => track.width = Some(read_uint(body)),
+                        PIXEL_HEIGHT => track.height = Some(read_uint(body)),
+                        _ => {}
+                    }
+                }
+            }
+            AUDIO => {
+                for (id, body) in Elements::new(body) {
+                    match id {
+                        SAMPLING_FREQUENCY => track.sample_rate = read_float(body),
+                        CHANNELS => track.channels = Some(read_uint(body)),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    track
+}
+
+fn parse_attachment(data: &[u8]) -> Option<Attachment> {
+    let mut file_name = None;
+    let mut mime_type = None;
+    let mut description = None;
+    let mut file_data = None;
+    for (id, body) in Elements::new(data) {
+        match id {
+            FILE_NAME => file_name = Some(read_string(body)),
+            FILE_MIME_TYPE => mime_type = Some(read_string(body)),
+            FILE_DESCRIPTION => description = Some(read_string(body)),
+            FILE_DATA => file_data = Some(body.to_vec()),
+            _ => {}
+        }
+    }
+    Some(Attachment {
+        file_name: file_name?,
+        mime_type: mime_type?,
+        description,
+        data: file_data?,
+    })
+}
+
+/// Reads the track entries of a Matroska file.
+pub fn read_tracks(path: &Path) -> io::Result<Vec<TrackMetadata>> {
+    let mut tracks = Vec::new();
+    for (_, data) in read_segment_elements(path, &[TRACKS])? {
+        for (id, track) in Elements::new(&data) {
+            if id == TRACK_ENTRY {
+                tracks.push(parse_track(track));
+            }
+        }
+    }
+    Ok(tracks)
+}
+
+/// Reads all string tags of a Matroska file.
+pub fn read_tags(path: &Path) -> io::Result<Vec<Tag>> {
+    let mut tags = Vec::new();
+    for (id, data) in read_segment_elements(path, &[TAGS])? {
+        if id == TAGS {
+            for (id, tag) in Elements::new(&data) {
+                if id == TAG {
+                    parse_tag(tag, &mut tags);
+                }
+            }
+        }
+    }
+    Ok(tags)
+}
+
+fn parse_tag(data: &[u8], tags: &mut Vec<Tag>) {
+    let mut track_uid = None;
+    for (id, body) in Elements::new(data) {
+        if id == TARGETS {
+            for (id, body) in Elements::new(body) {
+                if id == TAG_TRACK_UID && track_uid.is_none() {
+                    track_uid = Some(read_uint(body)).filter(|uid| *uid != 0);
+                }
+            }
+        }
+    }
+    for (id, body) in Elements::new(data) {
+        if id == SIMPLE_TAG {
+            parse_simple_tag(body, track_uid, tags);
+        }
+    }
+}
+
+fn parse_simple_tag(data: &[u8], track_uid: Option<u64>, tags: &mut Vec<Tag>) {
+    let mut name = None;
+    let mut value = None;
+    for (id, body) in Elements::new(data) {
+        match id {
+            TAG_NAME => name = Some(read_string(body)),
+            TAG_STRING => value = Some(read_string(body)),
+            // Nested tags refine their parent, e.g. a URL of a publisher.
+            SIMPLE_TAG => parse_simple_tag(body, track_uid, tags),
+            _ => {}
+        }
+    }
+    if let (Some(name), Some(value)) = (name, value) {
+        tags.push(Tag {
+            track_uid,
+            name,
+            value,
+        });
+    }
+}
+
+/// Keyframes of a track and the clusters holding them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeyframeIndex {
+    /// Offset of the first cluster in the file, everything before it are
+    /// headers.
+    pub clusters_start: u64,
+    /// Sorted by time.
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: Duration,
+    /// Offset of the cluster holding the keyframe in the file.
+    pub cluster: u64,
+}
+
+impl KeyframeIndex {
+    /// Returns the last keyframe at or before `time`.
+    pub fn before(&self, time: Duration) -> Option<Keyframe> {
+        let after = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        after.checked_sub(1).map(|index| self.keyframes[index])
+    }
+}
+
+/// Indexes the keyframes of the track with `track_number`, from the cues if
+/// the file has them and otherwise from the block headers of every cluster.
+///
+/// Returns `None` if `cancelled` returned `true` while reading clusters.
+pub fn read_keyframe_index(
+    path: &Path,
+    track_number: u64,
+    cancelled: impl Fn() -> bool,
+) -> io::Result<Option<KeyframeIndex>> {
+    let (mut reader, segment_start) = open_segment(path)?;
+    let mut timecode_scale = DEFAULT_TIMECODE_SCALE;
+    let mut cues_position = None;
+    let mut clusters_start = None;
+    // Cluster offsets and keyframe timecodes.
+    let mut keyframes = Vec::new();
+    let mut from_cues = false;
+    loop {
+        if cancelled() {
+            return Ok(None);
+        }
+        let position = reader.stream_position()?;
+        let (id, size) = match read_element_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        let size = match size {
+            Some(size) => size,
+            None => break,
+        };
+        match id {
+            INFO | SEEK_HEAD | CUES if size <= MAX_ELEMENT_SIZE => {
+                let mut data = vec![0; size as usize];
+                reader.read_exact(&mut data)?;
+                match id {
+                    INFO => {
+                        let scale = Elements::new(&data).find(|(id, _)| *id == TIMECODE_SCALE);
+                        if let Some((_, body)) = scale {
+                            timecode_scale = read_uint(body);
+                        }
+                    }
+                    SEEK_HEAD => cues_position = cues_position.or(parse_seek_head(&data)),
+                    _ => {
+                        let cues = parse_cues(&data, track_number, segment_start);
+                        if !cues.is_empty() {
+                            keyframes = cues;
+                            from_cues = true;
+                            // The offset of the first cluster is still needed.
+                            if clusters_start.is_some() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            CLUSTER => {
+                if clusters_start.is_none() {
+                    clusters_start = Some(position);
+                    if from_cues {
+                        break;
+                    }
+                    // Clusters only need to be read if the cues are missing.
+                    if let Some(cues) = cues_position {
+                        let cues = read_cues_at(&mut reader, segment_start + cues)?;
+                        keyframes = parse_cues(&cues, track_number, segment_start);
+                        if !keyframes.is_empty() {
+                            break;
+                        }
+                        reader.seek(SeekFrom::Start(position))?;
+                        continue;
+                    }
+                }
+                let end = reader.stream_position()? + size;
+                for timecode in read_cluster_keyframes(&mut reader, end, track_number)? {
+                    keyframes.push((position, timecode));
+                }
+                seek_to(&mut reader, end)?;
+            }
+            _ => reader.seek_relative(size as i64)?,
+        }
+    }
+    let clusters_start = match clusters_start {
+        Some(clusters_start) => clusters_start,
+        None => return Ok(Some(KeyframeIndex::default())),
+    };
+    let mut keyframes: Vec<Keyframe> = keyframes
+        .into_iter()
+        .map(|(cluster, timecode)| Keyframe {
+            time: Duration::from_nanos(timecode.saturating_mul(timecode_scale)),
+            cluster,
+        })
+        .collect();
+    keyframes.sort_by_key(|keyframe| keyframe.time);
+    Ok(Some(KeyframeIndex {
+        clusters_start,
+        keyframes,
+    }))
+}
+
+/// Reads the body of the cues element at `position`, empty if there is
+/// none.
+fn read_cues_at<R: Read + Seek>(reader: &mut R, position: u64) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(position))?;
+    match read_element_header(reader)? {
+        (CUES, Some(size)) if size <= MAX_ELEMENT_SIZE => {
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data)?;
+            Ok(data)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Returns the position of the cues relative to the segment data.
+fn parse_seek_head(data: &[u8]) -> Option<u64> {
+    Elements::new(data)
+        .filter(|(id, _)| *id == SEEK)
+        .find_map(|(_, seek)| {
+            let mut target = None;
+            let mut position = None;
+            for (id, body) in Elements::new(seek) {
+                match id {
+                    SEEK_ID => target = Some(read_uint(body) as u32),
+                    SEEK_POSITION => position = Some(read_uint(body)),
+                    _ => {}
+                }
+            }
+            position.filter(|_| target == Some(CUES))
+        })
+}
+
+/// Returns the cluster offsets and timecodes of the cue points of the track.
+fn parse_cues(data: &[u8], track_number: u64, segment_start: u64) -> Vec<(u64, u64)> {
+    let mut keyframes = Vec::new();
+    for (_, point) in Elements::new(data).filter(|(id, _)| *id == CUE_POINT) {
+        let mut time = None;
+        let mut cluster = None;
+        for (id, body) in Elements::new(point) {
+            match id {
+                CUE_TIME => time = Some(read_uint(body)),
+                CUE_TRACK_POSITIONS => {
+                    let mut track = None;
+                    let mut position = None;
+                    for (id, body) in Elements::new(body) {
+                        match id {
+                            CUE_TRACK => track = Some(read_uint(body)),
+                            CUE_CLUSTER_POSITION => position = Some(read_uint(body)),
+                            _ => {}
+                        }
+                    }
+                    if track == Some(track_number) {
+                        cluster = cluster.or(position);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let (Some(time), Some(cluster)) = (time, cluster) {
+            keyframes.push((segment_start + cluster, time));
+        }
+    }
+    keyframes
+}
+
+/// Returns the timecodes of the keyframes of the track in the cluster body
+/// ending at `end`, reading only the block headers.
+fn read_cluster_keyframes<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    end: u64,
+    track_number: u64,
+) -> io::Result<Vec<u64>> {
+    let mut cluster_timecode = 0;
+    let mut keyframes = Vec::new();
+    let mut add = |block: BlockHeader, key: bool, cluster_timecode: u64| {
+        if block.track == track_number && key {
+            let timecode = cluster_timecode as i64 + i64::from(block.timecode);
+            keyframes.push(timecode.max(0) as u64);
+        }
+    };
+    while reader.stream_position()? < end {
+        let (id, size) = read_element_header(reader)?;
+        let size = match size {
+            Some(size) => size,
+            None => break,
+        };
+        let element_end = reader.stream_position()? + size;
+        match id {
+            TIMECODE if size <= 8 => {
+                let mut data = vec![0; size as usize];
+                reader.read_exact(&mut data)?;
+                cluster_timecode = read_uint(&data);
+            }
+            SIMPLE_BLOCK => {
+                let block = read_block_header(reader)?;
+                add(block, block.flags & 0x80 != 0, cluster_timecode);
+            }
+            BLOCK_GROUP => {
+                let mut block = None;
+                let mut referenced = false;
+                while reader.stream_position()? < element_end {
+                    let (id, size) = read_element_header(reader)?;
+                    let size = size.unwrap_or(0);
+                    let child_end = reader.stream_position()? + size;
+                    match id {
+                        BLOCK => block = Some(read_block_header(reader)?),
+                        REFERENCE_BLOCK => referenced = true,
+                        _ => {}
+                    }
+                    seek_to(reader, child_end)?;
+                }
+                if let Some(block) = block {
+                    add(block, !referenced, cluster_timecode);
+                }
+            }
+            _ => {}
+        }
+        seek_to(reader, element_end)?;
+    }
+    Ok(keyframes)
+}
+
+/// Moves to `position`, keeping the buffered data if it's close enough.
+fn seek_to<R: Read + Seek>(reader: &mut BufReader<R>, position: u64) -> io::Result<()> {
+    let current = reader.stream_position()?;
+    reader.seek_relative(position as i64 - current as i64)
+}
+
+#[derive(Clone, Copy)]
+struct BlockHeader {
+    track: u64,
+    /// Relative to the cluster.
+    timecode: i16,
+    flags: u8,
+}
+
+fn read_block_header<R: Read>(reader: &mut R) -> io::Result<BlockHeader> {
+    let (track, _) = read_vint(reader, true)?;
+    let mut header = [0; 3];
+    reader.read_exact(&mut header)?;
+    Ok(BlockHeader {
+        track,
+        timecode: i16::from_be_bytes([header[0], header[1]]),
+        flags: header[2],
+    })
+}
+
+/// Reads the top level elements of the segment with one of the given ids.
+///
+/// `ATTACHMENTS` is returned as its `ATTACHED_FILE` children, which are read
+/// one by one, so that a large attachment doesn't hide the others.
+fn read_segment_elements(path: &Path, ids: &[u32]) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let (mut reader, _) = open_segment(path)?;
+    let mut elements = Vec::new();
+    loop {
+        let (id, size) = match read_element_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        let size = match size {
+            Some(size) => size,
+            // Clusters of live streams don't know their size, there is no way
+            // to skip them.
+            None => break,
+        };
+        if id == ATTACHMENTS && ids.contains(&id) {
+            read_attached_files(&mut reader, size, &mut elements)?;
+        } else if ids.contains(&id) && id != CLUSTER && size <= MAX_ELEMENT_SIZE {
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data)?;
+            elements.push((id, data));
+        } else {
+            skip(&mut reader, Some(size))?;
+        }
+    }
+    Ok(elements)
+}
+
+/// Opens a Matroska file and reads up to the segment data, returns the
+/// reader and the offset of the segment data.
+fn open_segment(path: &Path) -> io::Result<(BufReader<File>, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let (id, size) = read_element_header(&mut reader)?;
+    if id != EBML_HEADER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an EBML file",
+        ));
+    }
+    skip(&mut reader, size)?;
+    let (id, _) = read_element_header(&mut reader)?;
+    if id != SEGMENT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing segment",
+        ));
+    }
+    let segment_start = reader.stream_position()?;
+    Ok((reader, segment_start))
+}
+
+/// Reads the `ATTACHED_FILE` children of an `ATTACHMENTS` element of `size`
+/// bytes.
+fn read_attached_files<R: Read + Seek>(
+    reader: &mut R,
+    size: u64,
+    elements: &mut Vec<(u32, Vec<u8>)>,
+) -> io::Result<()> {
+    let end = reader.stream_position()? + size;
+    while reader.stream_position()? < end {
+        let (id, size) = read_element_header(reader)?;
+        let size = match size {
+            Some(size) => size,
+            // Only the last attachment can have an unknown size.
+            None => break,
+        };
+        if id == ATTACHED_FILE && size <= MAX_ELEMENT_SIZE {
+            let mut data = vec![0; size as usize];
+            reader.read_exact(&mut data)?;
+            elements.push((id, data));
+        } else {
+            skip(reader, Some(size))?;
+        }
+    }
+    reader.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+fn skip<R: Seek>(reader: &mut R, size: Option<u64>) -> io::Result<()> {
+    if let Some(size) = size {
+        reader.seek(SeekFrom::Current(size as i64))?;
+    }
+    Ok(())
+}
+
+/// Reads an element id and size, the size is `None` if unknown.
+fn read_element_header<R: Read>(reader: &mut R) -> io::Result<(u32, Option<u64>)> {
+    let (id, _) = read_vint(reader, false)?;
+    let (size, unknown) = read_vint(reader, true)?;
+    Ok((id as u32, if unknown { None } else { Some(size) }))
+}
+
+/// Reads a variable length integer, optionally without its length marker.
+/// Also returns whether all value bits are set, which marks an unknown size.
+fn read_vint<R: Read>(reader: &mut R, strip_marker: bool) -> io::Result<(u64, bool)> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    let len = byte[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid EBML integer",
+        ));
+    }
+    let mut value = u64::from(byte[0]);
+    let mut all_ones = value == (0xFF >> len) | (0x100 >> len);
+    for _ in 1..len {
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | u64::from(byte[0]);
+        all_ones &= byte[0] == 0xFF;
+    }
+    if strip_marker {
+        value &= !(1 << (7 * len));
+    }
+    Ok((value, all_ones))
+}
+
+/// Iterates over the child elements of an element body.
+struct Elements<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Elements<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut cursor = io::Cursor::new(self.data);
+        let (id, size) = read_element_header(&mut cursor).ok()?;
+        let start = cursor.position() as usize;
+        let end = match size {
+            Some(size) => start.checked_add(size as usize)?,
+            None => self.data.len(),
+        };
+        if end > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+        let body = &self.data[start..end];
+        self.data = &self.data[end..];
+        Some((id, body))
+    }
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+}
+
+fn read_int(data: &[u8]) -> i64 {
+    let value = read_uint(data);
+    match data.len() {
+        // Sign extend from the highest bit of the data.
+        len @ 1..=7 => ((value << (64 - 8 * len)) as i64) >> (64 - 8 * len),
+        _ => value as i64,
+    }
+}
+
+fn read_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f64::from(f32::from_bits(read_uint(data) as u32))),
+        8 => Some(f64::from_bits(read_uint(data))),
+        _ => None,
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// Size field of an element of unknown size.
+    const UNKNOWN_SIZE: u64 = (1 << 56) - 1;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name))
+    }
+
+    /// Encodes an element header with an 8 byte size.
+    fn header(id: u32, size: u64) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let start = id.iter().position(|byte| *byte != 0).unwrap();
+        let mut data = id[start..].to_vec();
+        data.push(0x01);
+        data.extend_from_slice(&size.to_be_bytes()[1..]);
+        data
+    }
+
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = header(id, body.len() as u64);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn simple_tag(name: &str, value: &str) -> Vec<u8> {
+        let mut body = element(TAG_NAME, name.as_bytes());
+        body.extend(element(TAG_STRING, value.as_bytes()));
+        element(SIMPLE_TAG, &body)
+    }
+
+    fn attached_file(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = element(FILE_NAME, name.as_bytes());
+        body.extend(element(FILE_MIME_TYPE, b"application/octet-stream"));
+        body.extend(element(FILE_DATA, data));
+        element(ATTACHED_FILE, &body)
+    }
+
+    /// Writes a file with a segment of unknown size, `hole` bytes of zeros
+    /// are left unwritten after `before` so that huge elements stay sparse.
+    fn write_file(name: &str, before: &[u8], hole: u64, after: &[u8]) -> PathBuf {
+        let path = temp_path(name);
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&element(EBML_HEADER, &[])).unwrap();
+        file.write_all(&header(SEGMENT, UNKNOWN_SIZE)).unwrap();
+        file.write_all(before).unwrap();
+        file.seek(SeekFrom::Current(hole as i64)).unwrap();
+        file.write_all(after).unwrap();
+        path
+    }
+
+    fn attachment_names(metadata: &Metadata) -> Vec<&str> {
+        let attachments = metadata.attachments.iter();
+        attachments
+            .map(|attachment| attachment.file_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_repeated_tags() {
+        let mut tags = simple_tag("ARTIST", "first");
+        tags.extend(simple_tag("ARTIST", "second"));
+        tags.extend(simple_tag("TITLE", "title"));
+        let segment = element(TAGS, &element(TAG, &tags));
+        let path = write_file("metadata-tags.mkv", &segment, 0, &[]);
+        let metadata = read_metadata(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(metadata.tags["ARTIST"], ["first", "second"]);
+        assert_eq!(metadata.tags["TITLE"], ["title"]);
+    }
+
+    #[test]
+    fn skips_only_oversized_attachments() {
+        let small = attached_file("small", b"small");
+        // Larger than fits into a u32, the data is a hole in the file.
+        let huge_size = u64::from(u32::MAX) + 1;
+        let huge = header(ATTACHED_FILE, huge_size);
+        let last = attached_file("last", b"last");
+        let size = (small.len() + huge.len() + last.len()) as u64 + huge_size;
+        let before = [header(ATTACHMENTS, size), small, huge].concat();
+        let after = [
+            last,
+            element(TAGS, &element(TAG, &simple_tag("TITLE", "t"))),
+        ]
+        .concat();
+        let path = write_file("metadata-attachments.mkv", &before, huge_size, &after);
+        let metadata = read_metadata(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(attachment_names(&metadata), ["small", "last"]);
+        assert_eq!(metadata.attachments[1].data, b"last");
+        assert_eq!(metadata.tags["TITLE"], ["t"]);
+    }
+
+    #[test]
+    fn stops_at_attachment_of_unknown_size() {
+        let small = attached_file("small", b"small");
+        let unknown = header(ATTACHED_FILE, UNKNOWN_SIZE);
+        let rest = attached_file("ignored", b"ignored");
+        let size = (small.len() + unknown.len() + rest.len()) as u64;
+        let segment = [
+            header(ATTACHMENTS, size),
+            small,
+            unknown,
+            rest,
+            element(TAGS, &element(TAG, &simple_tag("TITLE", "t"))),
+        ]
+        .concat();
+        let path = write_file("metadata-unknown-size.mkv", &segment, 0, &[]);
+        let metadata = read_metadata(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(attachment_names(&metadata), ["small"]);
+        assert_eq!(metadata.tags["TITLE"], ["t"]);
+    }
+
+    fn cluster(timecode: u64, blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = element(TIMECODE, &timecode.to_be_bytes());
+        for block in blocks {
+            body.extend_from_slice(block);
+        }
+        element(CLUSTER, &body)
+    }
+
+    fn block(track: u8, timecode: i16, flags: u8) -> Vec<u8> {
+        let mut body = vec![0x80 | track];
+        body.extend_from_slice(&timecode.to_be_bytes());
+        body.push(flags);
+        body.extend_from_slice(&[0; 16]);
+        body
+    }
+
+    fn info() -> Vec<u8> {
+        element(INFO, &element(TIMECODE_SCALE, &1_000_000u64.to_be_bytes()))
+    }
+
+    /// Offset of the segment data in files written by `write_file`.
+    const SEGMENT_START: u64 = 24;
+
+    #[test]
+    fn indexes_keyframes_of_clusters() {
+        let first = cluster(
+            0,
+            &[
+                element(SIMPLE_BLOCK, &block(1, 0, 0x80)),
+                element(SIMPLE_BLOCK, &block(1, 40, 0)),
+                element(SIMPLE_BLOCK, &block(2, 10, 0x80)),
+            ],
+        );
+        let referenced = [
+            element(BLOCK, &block(1, 60, 0)),
+            element(REFERENCE_BLOCK, &[0xEC]),
+        ];
+        let second = cluster(
+            1000,
+            &[
+                element(BLOCK_GROUP, &element(BLOCK, &block(1, 20, 0))),
+                element(BLOCK_GROUP, &referenced.concat()),
+            ],
+        );
+        let clusters_start = SEGMENT_START + info().len() as u64;
+        let second_start = clusters_start + first.len() as u64;
+        let segment = [info(), first, second].concat();
+        let path = write_file("metadata-clusters.mkv", &segment, 0, &[]);
+        let index = read_keyframe_index(&path, 1, || false).unwrap();
+        std::fs::remove_file(&path).ok();
+        let index = index.unwrap();
+        assert_eq!(index.clusters_start, clusters_start);
+        let keyframe = |ms, cluster| Keyframe {
+            time: Duration::from_millis(ms),
+            cluster,
+        };
+        assert_eq!(
+            index.keyframes,
+            [keyframe(0, clusters_start), keyframe(1020, second_start)]
+        );
+        assert_eq!(
+            index.before(Duration::from_millis(1019)),
+            Some(index.keyframes[0])
+        );
+        assert_eq!(
+            index.before(Duration::from_millis(1020)),
+            Some(index.keyframes[1])
+        );
+    }
+
+    #[test]
+    fn indexes_keyframes_of_cues() {
+        let seek_head = |cues: u64| {
+            let mut seek = element(SEEK_ID, &CUES.to_be_bytes());
+            seek.extend(element(SEEK_POSITION, &cues.to_be_bytes()));
+            element(SEEK_HEAD, &element(SEEK, &seek))
+        };
+        // Without blocks, only the cues know the keyframes.
+        let clusters = [cluster(0, &[]), cluster(2000, &[])];
+        let headers_len = (seek_head(0).len() + info().len()) as u64;
+        let second = headers_len + clusters[0].len() as u64;
+        let cues_position = second + clusters[1].len() as u64;
+        let cue_point = |time: u64, track: u64, cluster: u64| {
+            let mut positions = element(CUE_TRACK, &track.to_be_bytes());
+            positions.extend(element(CUE_CLUSTER_POSITION, &cluster.to_be_bytes()));
+            let mut point = element(CUE_TIME, &time.to_be_bytes());
+            point.extend(element(CUE_TRACK_POSITIONS, &positions));
+            element(CUE_POINT, &point)
+        };
+        let cues = [
+            cue_point(0, 1, headers_len),
+            cue_point(2000, 1, second),
+            cue_point(2500, 2, second),
+        ]
+        .concat();
+        let segment = [
+            seek_head(cues_position),
+            info(),
+            clusters.concat(),
+            element(CUES, &cues),
+        ]
+        .concat();
+        let path = write_file("metadata-cues.mkv", &segment, 0, &[]);
+        let index = read_keyframe_index(&path, 1, || false).unwrap();
+        let cancelled = read_keyframe_index(&path, 1, || true).unwrap();
+        std::fs::remove_file(&path).ok();
+        let index = index.unwrap();
+        assert_eq!(index.clusters_start, SEGMENT_START + headers_len);
+        let times: Vec<_> = index.keyframes.iter().map(|k| k.time.as_millis()).collect();
+        assert_eq!(times, [0, 2000]);
+        assert_eq!(index.keyframes[1].cluster, SEGMENT_START + second);
+        assert_eq!(cancelled, None);
+    }
+}
@@ -0,0 +1,205 @@
+//! Level and spectrum analysis of the played audio, for visualizers.
+use std::f32::consts::PI;
+
+/// Default interval between levels, 20 updates per second.
+pub const DEFAULT_INTERVAL_MS: u64 = 50;
+/// Number of frames the spectrum is computed from, a power of two.
+const FFT_SIZE: usize = 1024;
+/// Number of logarithmically spaced spectrum bands.
+pub const SPECTRUM_BANDS: usize = 32;
+/// Lower edge of the first spectrum band.
+const MIN_FREQUENCY: f32 = 20.0;
+/// Levels are reported in dBFS and floored at this.
+const MIN_DB: f32 = -100.0;
+
+/// Levels of the audio played during one metering interval, in dBFS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioLevels {
+    /// RMS level of each channel.
+    pub rms: Vec<f32>,
+    /// Peak level of each channel.
+    pub peak: Vec<f32>,
+    /// Magnitude of `SPECTRUM_BANDS` bands from 20 Hz up to the Nyquist
+    /// frequency, of all channels mixed to mono.
+    pub spectrum: Vec<f32>,
+}
+
+/// Computes `AudioLevels` from interleaved samples every `interval` frames.
+pub struct Meter {
+    channels: usize,
+    interval: usize,
+    frames: usize,
+    squares: Vec<f64>,
+    peaks: Vec<f32>,
+    /// The last `FFT_SIZE` mono samples, `history_pos` is the oldest.
+    history: Vec<f32>,
+    history_pos: usize,
+    fft: Fft,
+    /// Range of FFT bins covered by each spectrum band.
+    bands: Vec<(usize, usize)>,
+}
+
+impl Meter {
+    pub fn new(channels: usize, rate: usize, interval: usize) -> Self {
+        let channels = channels.max(1);
+        let bin_width = rate as f32 / FFT_SIZE as f32;
+        let nyquist = rate as f32 / 2.0;
+        let edge = |band: usize| {
+            let ratio = (nyquist / MIN_FREQUENCY).max(1.0);
+            MIN_FREQUENCY * ratio.powf(band as f32 / SPECTRUM_BANDS as f32)
+        };
+        let bands = (0..SPECTRUM_BANDS)
+            .map(|band| {
+                let start = ((edge(band) / bin_width) as usize).clamp(1, FFT_SIZE / 2 - 1);
+                let end = ((edge(band + 1) / bin_width) as usize).clamp(start + 1, FFT_SIZE / 2);
+                (start, end)
+            })
+            .collect();
+        Self {
+            channels,
+            interval: interval.max(1),
+            frames: 0,
+            squares: vec![0.0; channels],
+            peaks: vec![0.0; channels],
+            history: vec![0.0; FFT_SIZE],
+            history_pos: 0,
+            fft: Fft::new(FFT_SIZE),
+            bands,
+        }
+    }
+
+    /// Number of frames between levels.
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    /// Adds interleaved samples, returns the levels of the last completed
+    /// interval if one completed.
+    pub fn process(&mut self, samples: &[f32]) -> Option<AudioLevels> {
+        let mut levels = None;
+        for frame in samples.chunks_exact(self.channels) {
+            let mut mono = 0.0;
+            for (ch, sample) in frame.iter().enumerate() {
+                self.squares[ch] += f64::from(sample * sample);
+                self.peaks[ch] = self.peaks[ch].max(sample.abs());
+                mono += sample;
+            }
+            self.history[self.history_pos] = mono / self.channels as f32;
+            self.history_pos = (self.history_pos + 1) % FFT_SIZE;
+            self.frames += 1;
+            if self.frames == self.interval {
+                levels = Some(self.finish_interval());
+            }
+        }
+        levels
+    }
+
+    fn finish_interval(&mut self) -> AudioLevels {
+        let frames = self.frames as f64;
+        let rms = self
+            .squares
+            .iter()
+            .map(|squares| decibels((squares / frames).sqrt() as f32))
+            .collect();
+        let peak = self.peaks.iter().map(|peak| decibels(*peak)).collect();
+        for value in self.squares.iter_mut() {
+            *value = 0.0;
+        }
+        for value in self.peaks.iter_mut() {
+            *value = 0.0;
+        }
+        self.frames = 0;
+        AudioLevels {
+            rms,
+            peak,
+            spectrum: self.spectrum(),
+        }
+    }
+
+    fn spectrum(&mut self) -> Vec<f32> {
+        let (newest, oldest) = self.history.split_at(self.history_pos);
+        let magnitudes = self.fft.magnitudes(oldest.iter().chain(newest));
+        self.bands
+            .iter()
+            .map(|(start, end)| {
+                let magnitude = magnitudes[*start..*end]
+                    .iter()
+                    .fold(0.0f32, |max, magnitude| max.max(*magnitude));
+                decibels(magnitude)
+            })
+            .collect()
+    }
+}
+
+fn decibels(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(MIN_DB)
+}
+
+/// Radix-2 FFT of real input with a Hann window.
+struct Fft {
+    window: Vec<f32>,
+    /// `cos` and `sin` of the twiddle factors for the largest stage.
+    twiddles: Vec<(f32, f32)>,
+    re: Vec<f32>,
+    im: Vec<f32>,
+    magnitudes: Vec<f32>,
+}
+
+impl Fft {
+    fn new(size: usize) -> Self {
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect();
+        let twiddles = (0..size / 2)
+            .map(|i| {
+                let angle = -2.0 * PI * i as f32 / size as f32;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        Self {
+            window,
+            twiddles,
+            re: vec![0.0; size],
+            im: vec![0.0; size],
+            magnitudes: vec![0.0; size / 2],
+        }
+    }
+
+    /// Returns the amplitude of each bin up to the Nyquist frequency, scaled
+    /// so a full scale sine has an amplitude of about 1.
+    fn magnitudes<'a>(&mut self, input: impl Iterator<Item = &'a f32>) -> &[f32] {
+        let size = self.re.len();
+        let bits = size.trailing_zeros();
+        for (i, (sample, window)) in input.zip(&self.window).enumerate() {
+            let j = i.reverse_bits() >> (std::mem::size_of::<usize>() as u32 * 8 - bits);
+            self.re[j] = sample * window;
+            self.im[j] = 0.0;
+        }
+
+        let mut len = 2;
+        while len <= size {
+            let step = size / len;
+            for start in (0..size).step_by(len) {
+                for k in 0..len / 2 {
+                    let (cos, sin) = self.twiddles[k * step];
+                    let (a, b) = (start + k, start + k + len / 2);
+                    let re = self.re[b] * cos - self.im[b] * sin;
+                    let im = self.re[b] * sin + self.im[b] * cos;
+                    self.re[b] = self.re[a] - re;
+                    self.im[b] = self.im[a] - im;
+                    self.re[a] += re;
+                    self.im[a] += im;
+                }
+            }
+            len *= 2;
+        }
+
+        // The Hann window halves the amplitude, a real sine splits into two
+        // bins.
+        let scale = 4.0 / size as f32;
+        for (i, magnitude) in self.magnitudes.iter_mut().enumerate() {
+            *magnitude = self.re[i].hypot(self.im[i]) * scale;
+        }
+        &self.magnitudes
+    }
+}
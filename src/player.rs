@@ -1,24 +1,221 @@
-use crate::audio::{AudioPlayer, AudioStream};
-use crate::video::{VideoPlayer, VideoStream};
+//! Playback of media files, usable without Flutter.
+//!
+//! ```no_run
+//! use flutter_video_plugin::{AudioSink, MemoryFrameSink, Player, PlayerEvent};
+//!
+//! let frames = MemoryFrameSink::new();
+//! let player = Player::builder()
+//!     .path("video.webm")
+//!     .frame_sink(frames.clone())
+//!     .audio_sink(AudioSink::Null { realtime: true })
+//!     .build()?;
+//! let events = player.events();
+//! // The first frame was posted to `frames`.
+//! while let Ok(event) = events.recv() {
+//!     if let PlayerEvent::Initialized { .. } = event {
+//!         break;
+//!     }
+//! }
+//! player.play()?;
+//! # Ok::<(), flutter_video_plugin::PlayerError>(())
+//! ```
+use crate::audio::{self, AudioEvent, AudioPlayer, AudioStats, AudioStream};
+use crate::dsp::{AudioEffect, EqualizerBand};
+use crate::event::PlayerEvent;
+use crate::loudness::{self, LoudnessMeter};
+use crate::metadata::{self, KeyframeIndex, Metadata};
+use crate::queue::{timestamp, Decoded, Queued, ReadAhead, Seek, SeekTarget, SharedQueue};
+use crate::sink::AudioSink;
+use crate::snapshot::{ImageFormat, VideoFrame};
+use crate::video::{FrameSink, VideoPlayer, VideoStream};
 use av_codec::common::CodecList;
 use av_codec::decoder::Codecs as DecCodecs;
 use av_codec::decoder::Context as DecContext;
 use av_data::frame::ArcFrame;
 pub use av_data::frame::MediaKind;
+use av_data::packet::Packet;
 use av_data::params;
+use av_data::rational::Rational64;
 use av_format::buffer::AccReader;
 use av_format::demuxer::*;
 use av_vorbis::decoder::VORBIS_DESCR;
-use flutter_engine::texture_registry::Texture;
+use crossbeam::channel::{self, select, Receiver, Sender, TryRecvError};
 use flutter_plugins::prelude::*;
+use image::RgbaImage;
 use libopus::decoder::OPUS_DESCR;
 use libvpx::decoder::VP9_DESCR;
 use matroska::demuxer::MkvDemuxer;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::Path;
-use std::sync::mpsc;
-use std::thread;
+use std::io::{self, Read, Seek as _, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Slowest supported playback speed.
+pub const MIN_SPEED: f64 = 0.25;
+/// Fastest supported playback speed.
+pub const MAX_SPEED: f64 = 4.0;
+
+/// Interval between `PlayerEvent::Position` events while playing.
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+/// Decoded frames queued for the video thread, the packet queues buffer
+/// further ahead.
+const VIDEO_FRAMES: usize = 4;
+/// Decoded frames queued for the audio worker.
+const AUDIO_FRAMES: usize = 16;
+/// How long the demuxer waits when the packet queues are full.
+const DEMUXER_POLL_INTERVAL: Duration = Duration::from_millis(2);
+/// How often a stream decoder waiting for packets checks for cancellation.
+const DECODER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State of a `Player`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlayerState {
+    /// The file is being opened and the first video frame decoded.
+    Initializing,
+    Paused,
+    Playing,
+    /// Playing, but waiting for decoded audio.
+    Buffering,
+    /// Every stream played to its end.
+    Ended,
+    /// Decoding failed, the player can only be disposed.
+    Error,
+    /// The player was disposed and can't be used anymore.
+    Disposed,
+}
+
+impl PlayerState {
+    /// Whether the state may change from `self` to `to`.
+    fn can_change_to(self, to: PlayerState) -> bool {
+        use PlayerState::*;
+        match (self, to) {
+            (Disposed, _) => false,
+            (_, Disposed) => true,
+            (Error, _) => false,
+            (_, Error) => true,
+            // Initializing only ends through `StateMachine::initialize`, so
+            // pausing can't skip the initialized events.
+            (Paused, Playing) | (Playing, Paused) | (Buffering, Paused) => true,
+            (Playing, Buffering) | (Buffering, Playing) => true,
+            (Paused, Ended) | (Playing, Ended) | (Buffering, Ended) => true,
+            // Seeking away from the end.
+            (Ended, Paused) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for PlayerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Initializing => "initializing",
+            Self::Paused => "paused",
+            Self::Playing => "playing",
+            Self::Buffering => "buffering",
+            Self::Ended => "ended",
+            Self::Error => "failed",
+            Self::Disposed => "disposed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The authoritative state of a `Player`, validates changes and publishes
+/// them as `PlayerEvent::StateChanged`.
+struct StateMachine {
+    state: Mutex<PlayerState>,
+    events: Sender<PlayerEvent>,
+}
+
+impl StateMachine {
+    fn new(events: Sender<PlayerEvent>) -> Self {
+        Self {
+            state: Mutex::new(PlayerState::Initializing),
+            events,
+        }
+    }
+
+    fn get(&self) -> PlayerState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Fails if `action` can't move the player to `to`.
+    fn check_change(&self, action: &'static str, to: PlayerState) -> Result<(), PlayerError> {
+        let state = self.get();
+        if state == to || state.can_change_to(to) {
+            Ok(())
+        } else {
+            Err(PlayerError::InvalidState { action, state })
+        }
+    }
+
+    /// Fails if the player was disposed or failed.
+    fn check_usable(&self, action: &'static str) -> Result<(), PlayerError> {
+        match self.get() {
+            state @ PlayerState::Disposed | state @ PlayerState::Error => {
+                Err(PlayerError::InvalidState { action, state })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Moves to `to` on behalf of `action`.
+    fn change(&self, action: &'static str, to: PlayerState) -> Result<(), PlayerError> {
+        let mut state = self.state.lock().unwrap();
+        if *state == to {
+            return Ok(());
+        }
+        if !state.can_change_to(to) {
+            return Err(PlayerError::InvalidState {
+                action,
+                state: *state,
+            });
+        }
+        log::debug!("player state {} -> {}", state, to);
+        *state = to;
+        self.events.send(PlayerEvent::StateChanged(to)).ok();
+        Ok(())
+    }
+
+    /// Moves to `to` if the current state is one of `from`, for changes
+    /// caused by playback rather than by calls.
+    fn advance(&self, from: &[PlayerState], to: PlayerState) {
+        if from.contains(&self.get()) {
+            self.change("advance", to).ok();
+        }
+    }
+
+    /// Publishes `events` and moves to `Paused` if the player is still
+    /// initializing, the events are dropped if it failed or was disposed.
+    fn initialize(&self, events: impl IntoIterator<Item = PlayerEvent>) {
+        let mut state = self.state.lock().unwrap();
+        if *state != PlayerState::Initializing {
+            return;
+        }
+        for event in events {
+            self.events.send(event).ok();
+        }
+        // Calls made after the events arrived see the new state.
+        log::debug!("player state {} -> {}", state, PlayerState::Paused);
+        *state = PlayerState::Paused;
+        self.events
+            .send(PlayerEvent::StateChanged(PlayerState::Paused))
+            .ok();
+    }
+
+    /// Moves to the error state and publishes `message`.
+    fn fail(&self, message: String) {
+        self.events.send(PlayerEvent::Error(message)).ok();
+        self.change("fail", PlayerState::Error).ok();
+    }
+}
 
 #[derive(Debug)]
 pub enum PlayerError {
@@ -26,6 +223,24 @@ pub enum PlayerError {
     Codec(av_codec::error::Error),
     Audio(crate::audio::AudioError),
     Io(std::io::Error),
+    InvalidSpeed(f64),
+    /// `PlayerBuilder::build` was called without a source.
+    NoSource,
+    /// Stepping through or capturing frames of a file without video.
+    NoVideo,
+    /// Capturing a frame before one was presented, or no frame was decoded
+    /// for a thumbnail.
+    NoFrame,
+    /// Encoding a frame failed.
+    Image(image::ImageError),
+    /// The demuxer reported something other than a packet or the end of the
+    /// file, such as a stream appearing mid-file.
+    UnsupportedEvent(String),
+    /// `action` isn't possible in `state`.
+    InvalidState {
+        action: &'static str,
+        state: PlayerState,
+    },
 }
 
 impl std::fmt::Display for PlayerError {
@@ -35,6 +250,19 @@ impl std::fmt::Display for PlayerError {
             Self::Codec(err) => err.fmt(f),
             Self::Audio(err) => err.fmt(f),
             Self::Io(err) => err.fmt(f),
+            Self::InvalidSpeed(speed) => write!(
+                f,
+                "playback speed {} outside of {}..={}",
+                speed, MIN_SPEED, MAX_SPEED
+            ),
+            Self::NoSource => write!(f, "no source to play"),
+            Self::NoVideo => write!(f, "no video stream"),
+            Self::NoFrame => write!(f, "no video frame available"),
+            Self::Image(err) => err.fmt(f),
+            Self::UnsupportedEvent(event) => write!(f, "unsupported demuxer event {}", event),
+            Self::InvalidState { action, state } => {
+                write!(f, "cannot {} while the player is {}", action, state)
+            }
         }
     }
 }
@@ -58,6 +286,12 @@ impl From<crate::audio::AudioError> for PlayerError {
     }
 }
 
+impl From<image::ImageError> for PlayerError {
+    fn from(error: image::ImageError) -> Self {
+        Self::Image(error)
+    }
+}
+
 impl From<std::io::Error> for PlayerError {
     fn from(error: std::io::Error) -> Self {
         Self::Io(error)
@@ -70,26 +304,39 @@ impl From<PlayerError> for MethodCallError {
     }
 }
 
-struct PlaybackContext {
+/// Demuxes a file and decodes its first video and audio stream.
+pub struct PlaybackContext {
     decoders: HashMap<isize, DecContext>,
     demuxer: Context,
+    video_index: Option<isize>,
+    audio_index: Option<isize>,
+    eof: bool,
+    path: PathBuf,
+    /// Packets before this time are skipped after a seek.
+    skip_until: Option<Duration>,
+    /// Keyframes seeks start at, read on the first seek.
+    keyframes: Option<KeyframeIndex>,
     pub video: Option<params::VideoInfo>,
     pub audio: Option<params::AudioInfo>,
 }
 
 impl PlaybackContext {
+    /// Opens a Matroska or WebM file and sets up decoders for its streams.
     pub fn from_path(path: &Path) -> Result<Self, PlayerError> {
-        let r = File::open(path)?;
-        let ar = AccReader::with_capacity(4 * 1024, r);
-
-        let mut c = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
+        Self::with_video_threads(path, 0)
+    }
 
-        c.read_headers()?;
+    /// Like `from_path`, the video is decoded with `threads` threads if the
+    /// decoder supports it, 0 leaves the choice to the decoder.
+    pub fn with_video_threads(path: &Path, threads: usize) -> Result<Self, PlayerError> {
+        let c = open_demuxer(path)?;
 
         let decoders = DecCodecs::from_list(&[VP9_DESCR, OPUS_DESCR, VORBIS_DESCR]);
 
         let mut video_info = None;
+        let mut video_index = None;
         let mut audio_info = None;
+        let mut audio_index = None;
         let mut decs: HashMap<isize, DecContext> = HashMap::with_capacity(2);
         for st in &c.info.streams {
             // TODO stream selection
@@ -98,14 +345,22 @@ impl PlaybackContext {
                     if let Some(ref extradata) = st.params.extradata {
                         ctx.set_extradata(extradata);
                     }
+                    let is_video = matches!(st.params.kind, Some(params::MediaKind::Video(_)));
+                    if is_video && threads > 0 {
+                        if let Err(err) = ctx.set_option("threads", threads as u64) {
+                            log::warn!("failed to set video decoder threads: {}", err);
+                        }
+                    }
                     ctx.configure()?;
                     decs.insert(st.index as isize, ctx);
                     match st.params.kind {
                         Some(params::MediaKind::Video(ref info)) => {
                             video_info = Some(info.clone());
+                            video_index = Some(st.index as isize);
                         }
                         Some(params::MediaKind::Audio(ref info)) => {
                             audio_info = Some(info.clone());
+                            audio_index = Some(st.index as isize);
                         }
                         _ => {}
                     }
@@ -116,97 +371,873 @@ impl PlaybackContext {
         Ok(Self {
             decoders: decs,
             demuxer: c,
+            video_index,
+            audio_index,
+            eof: false,
+            path: path.to_path_buf(),
+            skip_until: None,
+            keyframes: None,
             video: video_info,
             audio: audio_info,
         })
     }
 
+    /// Reads the next packet and decodes it, returns `None` if the packet
+    /// didn't produce a frame or belongs to a stream without decoder.
     pub fn decode_one(&mut self) -> Result<Option<ArcFrame>, PlayerError> {
+        match self.read_packet()? {
+            Some(pkt) => self.decode(&pkt),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the next packet of the played streams, returns `None` for
+    /// packets of other streams and at the end of the file.
+    pub fn read_packet(&mut self) -> Result<Option<Packet>, PlayerError> {
         match self.demuxer.read_event()? {
             Event::NewPacket(pkt) => {
-                if let Some(dec) = self.decoders.get_mut(&pkt.stream_index) {
-                    dec.send_packet(&pkt)?;
-                    Ok(dec.receive_frame().ok())
+                let index = Some(pkt.stream_index);
+                let time = timestamp(pkt.t.pts, pkt.t.timebase);
+                if self.skip_until.is_some() && time < self.skip_until {
+                    Ok(None)
+                } else if index == self.video_index || index == self.audio_index {
+                    self.skip_until = None;
+                    Ok(Some(pkt))
                 } else {
-                    println!("Skipping packet at index {}", pkt.stream_index);
+                    log::trace!("Skipping packet at index {}", pkt.stream_index);
                     Ok(None)
                 }
             }
-            Event::Eof => Ok(None),
-            event => {
-                println!("Unsupported event {:?}", event);
-                unimplemented!();
+            Event::Eof => {
+                self.eof = true;
+                Ok(None)
+            }
+            event => Err(PlayerError::UnsupportedEvent(format!("{:?}", event))),
+        }
+    }
+
+    /// Continues reading at the last keyframe at or before `time`.
+    ///
+    /// The demuxer can't seek, so it is reopened on a view of the file in
+    /// which the cluster of the keyframe follows the headers.
+    pub fn seek(&mut self, time: Duration) -> Result<(), PlayerError> {
+        self.index_keyframes(|| false);
+        let index = self.keyframes.as_ref();
+        let keyframe = index.and_then(|index| index.before(time));
+        self.demuxer = match (index, keyframe) {
+            (Some(index), Some(keyframe)) => {
+                open_demuxer_at(&self.path, index.clusters_start, keyframe.cluster)?
+            }
+            _ => open_demuxer(&self.path)?,
+        };
+        self.skip_until = keyframe.map(|keyframe| keyframe.time);
+        self.eof = false;
+        Ok(())
+    }
+
+    /// Indexes the keyframes of the video stream, or of the audio stream if
+    /// there is no video, unless that happened before.
+    ///
+    /// Returns `false` if `cancelled` returned `true` before the index was
+    /// complete. Without an index, seeks read the file from the start.
+    pub fn index_keyframes(&mut self, cancelled: impl Fn() -> bool) -> bool {
+        if self.keyframes.is_some() {
+            return true;
+        }
+        let track_number = match self.stream_track_number(self.video_index.or(self.audio_index)) {
+            Some(track_number) => track_number,
+            None => {
+                self.keyframes = Some(KeyframeIndex::default());
+                return true;
+            }
+        };
+        match metadata::read_keyframe_index(&self.path, track_number, cancelled) {
+            Ok(Some(index)) => self.keyframes = Some(index),
+            Ok(None) => return false,
+            Err(err) => {
+                log::warn!("failed to index keyframes: {}", err);
+                self.keyframes = Some(KeyframeIndex::default());
+            }
+        }
+        true
+    }
+
+    /// Decodes a packet returned by `read_packet`, returns `None` if it
+    /// didn't produce a frame.
+    pub fn decode(&mut self, pkt: &Packet) -> Result<Option<ArcFrame>, PlayerError> {
+        match self.decoders.get_mut(&pkt.stream_index) {
+            Some(dec) => {
+                dec.send_packet(pkt)?;
+                Ok(dec.receive_frame().ok())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Index of the decoded video stream in the container.
+    pub fn video_index(&self) -> Option<usize> {
+        self.video_index.map(|index| index as usize)
+    }
+
+    /// Index of the decoded audio stream in the container.
+    pub fn audio_index(&self) -> Option<usize> {
+        self.audio_index.map(|index| index as usize)
+    }
+
+    /// Matroska track number of the audio stream being decoded.
+    pub fn audio_track_number(&self) -> Option<u64> {
+        self.stream_track_number(self.audio_index)
+    }
+
+    fn stream_track_number(&self, index: Option<isize>) -> Option<u64> {
+        let index = index? as usize;
+        let streams = &self.demuxer.info.streams;
+        let stream = streams.iter().find(|st| st.index == index)?;
+        Some(stream.id as u64)
+    }
+
+    /// Duration of the file if the container stores it.
+    pub fn duration(&self) -> Option<Duration> {
+        let info = &self.demuxer.info;
+        let (duration, timebase) = (info.duration?, info.timebase?);
+        let nanos = Rational64::from_integer(duration as i64 * 1_000_000_000) * timebase;
+        Some(Duration::from_nanos(nanos.to_integer().max(0) as u64))
+    }
+
+    /// Whether the demuxer reached the end of the file.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Stops decoding video, for tasks that only need the audio.
+    pub fn disable_video(&mut self) {
+        if let Some(index) = self.video_index.take() {
+            self.decoders.remove(&index);
+        }
+        self.video = None;
+    }
+
+    /// Stops decoding audio, for tasks that only need the video.
+    pub fn disable_audio(&mut self) {
+        if let Some(index) = self.audio_index.take() {
+            self.decoders.remove(&index);
+        }
+        self.audio = None;
+    }
+}
+
+/// Opens a Matroska or WebM file and reads its headers.
+fn open_demuxer(path: &Path) -> Result<Context, PlayerError> {
+    open_demuxer_at(path, 0, 0)
+}
+
+/// Like `open_demuxer`, but the demuxer continues after the headers with
+/// the cluster at offset `cluster` instead of the one at `clusters_start`.
+fn open_demuxer_at(path: &Path, clusters_start: u64, cluster: u64) -> Result<Context, PlayerError> {
+    let r = SkipBytes {
+        inner: File::open(path)?,
+        start: clusters_start,
+        len: cluster.saturating_sub(clusters_start),
+        position: 0,
+    };
+    let ar = AccReader::with_capacity(4 * 1024, r);
+    let mut c = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
+    c.read_headers()?;
+    Ok(c)
+}
+
+/// Reads `inner` without the `len` bytes at offset `start`.
+struct SkipBytes<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    /// Position in the shortened stream.
+    position: u64,
+}
+
+impl<R> SkipBytes<R> {
+    /// Offset in `inner` of `position`.
+    fn inner_position(&self, position: u64) -> u64 {
+        if position < self.start {
+            position
+        } else {
+            position + self.len
+        }
+    }
+}
+
+impl<R: Read + io::Seek> Read for SkipBytes<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = if self.position < self.start {
+            buf.len().min((self.start - self.position) as usize)
+        } else {
+            buf.len()
+        };
+        let read = self.inner.read(&mut buf[..limit])?;
+        self.position += read as u64;
+        if self.len > 0 && self.position == self.start && read > 0 {
+            self.inner.seek(SeekFrom::Start(self.start + self.len))?;
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Read + io::Seek> io::Seek for SkipBytes<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => checked_offset(self.position, offset),
+            SeekFrom::End(offset) => {
+                let end = self.inner.seek(SeekFrom::End(0))?.saturating_sub(self.len);
+                checked_offset(end, offset)
             }
+        };
+        let position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start"))?;
+        self.inner
+            .seek(SeekFrom::Start(self.inner_position(position)))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+fn checked_offset(position: u64, offset: i64) -> Option<u64> {
+    if offset < 0 {
+        position.checked_sub(offset.unsigned_abs())
+    } else {
+        position.checked_add(offset as u64)
+    }
+}
+
+/// Returns the gain in dB from the ReplayGain or R128 tags of the audio track
+/// with `track_number` or of the whole file.
+fn tagged_gain(path: &Path, track_number: Option<u64>) -> io::Result<Option<f64>> {
+    let tags = metadata::read_tags(path)?;
+    let track_uid = metadata::read_tracks(path)?
+        .into_iter()
+        .find(|track| Some(track.number) == track_number)
+        .and_then(|track| track.uid);
+    Ok(loudness::gain_from_tags(&tags, track_uid))
+}
+
+/// Returns the loudness normalization gain in dB from the track's ReplayGain
+/// or R128 tags, or by measuring the integrated loudness if it has none.
+///
+/// Returns `None` if the audio is silent or `cancelled` returned `true`.
+fn normalization_gain(
+    path: &Path,
+    cancelled: impl Fn() -> bool,
+) -> Result<Option<f64>, PlayerError> {
+    let mut context = PlaybackContext::from_path(path)?;
+    match tagged_gain(path, context.audio_track_number()) {
+        Ok(Some(gain)) => return Ok(Some(gain)),
+        Ok(None) => {}
+        Err(err) => log::debug!("failed to read tags: {}", err),
+    }
+
+    context.disable_video();
+    let info = match context.audio.take() {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    let channels = info.map.as_ref().map(|map| map.len()).unwrap_or(1);
+    let mut meter = LoudnessMeter::new(channels, info.rate);
+    let mut samples = Vec::new();
+    while !context.is_eof() {
+        if cancelled() {
+            return Ok(None);
+        }
+        if let Some(frame) = context.decode_one()? {
+            audio::read_samples(&frame, &mut samples);
+            meter.process(&samples);
         }
     }
+    Ok(meter.integrated().map(loudness::gain_from_loudness))
 }
 
+/// Plays a media file, decoding on background threads and sending video
+/// frames to a `FrameSink` and audio to an `AudioSink`.
 pub struct Player {
-    audio: Option<AudioStream>,
+    path: PathBuf,
+    audio: Option<Arc<AudioStream>>,
     video: Option<VideoStream>,
+    events: Receiver<PlayerEvent>,
+    state: Arc<StateMachine>,
+    /// Dropped to cancel the decoder and the event pump.
+    cancel: Option<Sender<()>>,
+    seeks: Sender<Seek>,
+    seek_generation: AtomicU64,
+    /// Set while the video was stepped away from the audio.
+    stepped: AtomicBool,
+    threads: Vec<JoinHandle<()>>,
+    /// Looks up the normalization gain, started on first use.
+    loudness: Mutex<Option<JoinHandle<()>>>,
     width: i64,
     height: i64,
 }
 
-impl Player {
-    pub fn from_path(path: &Path, texture: Texture) -> Result<Self, PlayerError> {
-        let mut context = PlaybackContext::from_path(path)?;
-        let (v_s, v_r) = mpsc::sync_channel(24);
-        let (a_s, a_r) = mpsc::channel();
-
-        let audio_info = context.audio.take().expect("audio channel");
-        let audio = AudioPlayer::new(&audio_info)?;
-        let audio_stream = audio.create_stream(a_r)?;
-
-        let video_info = context.video.take().expect("video channel");
-        let video = VideoPlayer::new(&video_info, texture);
-        let video_stream = video.create_stream(v_r);
-
-        // decoder task
-        thread::spawn(move || loop {
-            if let Ok(Some(frame)) = context.decode_one() {
-                match frame.kind {
-                    MediaKind::Video(_) => {
-                        if v_s.send(frame).is_err() {
+/// Configures and creates a `Player`, see `Player::builder`.
+pub struct PlayerBuilder {
+    path: Option<PathBuf>,
+    frame_sink: Option<Box<dyn FrameSink>>,
+    audio_sink: AudioSink,
+    audio_device: Option<String>,
+    effects: Vec<Box<dyn AudioEffect>>,
+    volume: f64,
+    muted: bool,
+    speed: f64,
+    read_ahead: ReadAhead,
+    video_threads: usize,
+}
+
+impl PlayerBuilder {
+    /// The Matroska or WebM file to play.
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Where video frames are posted, they are discarded if not set.
+    pub fn frame_sink(mut self, sink: impl FrameSink + 'static) -> Self {
+        self.frame_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Where audio is played, the output device by default.
+    pub fn audio_sink(mut self, sink: AudioSink) -> Self {
+        self.audio_sink = sink;
+        self
+    }
+
+    /// Name of the output device, see `output_devices`. The default device
+    /// is used if not set.
+    pub fn audio_device(mut self, device: impl Into<String>) -> Self {
+        self.audio_device = Some(device.into());
+        self
+    }
+
+    /// Adds an effect after the equalizer and before the limiter.
+    pub fn audio_effect(mut self, effect: Box<dyn AudioEffect>) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    pub fn volume(mut self, volume: f64) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn muted(mut self, muted: bool) -> Self {
+        self.muted = muted;
+        self
+    }
+
+    /// Playback speed between `MIN_SPEED` and `MAX_SPEED`.
+    pub fn playback_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// How far the file is demuxed ahead of playback.
+    pub fn read_ahead(mut self, read_ahead: ReadAhead) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Number of threads the video is decoded with, by default the decoder
+    /// chooses.
+    pub fn video_decoder_threads(mut self, threads: usize) -> Self {
+        self.video_threads = threads;
+        self
+    }
+
+    /// Opens the file and starts decoding. The player is initializing until
+    /// the first video frame was presented, then paused.
+    pub fn build(self) -> Result<Player, PlayerError> {
+        let path = self.path.ok_or(PlayerError::NoSource)?;
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed) {
+            return Err(PlayerError::InvalidSpeed(self.speed));
+        }
+        let frame_sink = self.frame_sink.unwrap_or_else(|| Box::new(DiscardFrames));
+        let player = Player::new(
+            &path,
+            frame_sink,
+            &self.audio_sink,
+            self.audio_device.as_deref(),
+            self.effects,
+            self.read_ahead,
+            self.video_threads,
+        )?;
+        player.set_volume(self.volume);
+        player.set_muted(self.muted);
+        player.set_playback_speed(self.speed)?;
+        Ok(player)
+    }
+}
+
+/// Translates the events of the streams to `PlayerEvent`s and reports the
+/// position and completion of playback.
+struct EventPump {
+    audio_events: Receiver<AudioEvent>,
+    audio: Option<Weak<AudioStream>>,
+    video_ended: Option<Arc<AtomicBool>>,
+    state: Arc<StateMachine>,
+    /// Disconnects once the `Player` is dropped.
+    cancel: Receiver<()>,
+    /// Receives once the first video frame was presented, disconnects
+    /// without if none was.
+    prerolled: Receiver<()>,
+    /// Sent after the first video frame was presented.
+    initialized: Vec<PlayerEvent>,
+    events: Sender<PlayerEvent>,
+}
+
+impl EventPump {
+    fn run(mut self) {
+        let presented = select! {
+            recv(self.prerolled) -> presented => presented.is_ok(),
+            recv(self.cancel) -> _ => return,
+        };
+        if !presented && self.video_ended.is_some() {
+            if let Err(TryRecvError::Disconnected) = self.cancel.try_recv() {
+                return;
+            }
+            self.state
+                .fail("no video frame could be decoded".to_string());
+            return;
+        }
+        self.state.initialize(self.initialized.drain(..));
+
+        let mut completed = false;
+        let mut last_position = Instant::now();
+        loop {
+            let received = select! {
+                recv(self.audio_events) -> event => Some(event),
+                recv(self.cancel) -> _ => break,
+                default(POSITION_INTERVAL) => None,
+            };
+            let event = match received {
+                Some(Ok(AudioEvent::BufferingStart)) => {
+                    self.state
+                        .advance(&[PlayerState::Playing], PlayerState::Buffering);
+                    Some(PlayerEvent::Buffering(true))
+                }
+                Some(Ok(AudioEvent::BufferingEnd)) => {
+                    self.state
+                        .advance(&[PlayerState::Buffering], PlayerState::Playing);
+                    Some(PlayerEvent::Buffering(false))
+                }
+                Some(Ok(AudioEvent::DeviceChanged(device))) => {
+                    Some(PlayerEvent::AudioDeviceChanged(device))
+                }
+                Some(Ok(AudioEvent::Levels(levels))) => Some(PlayerEvent::AudioLevels(levels)),
+                Some(Ok(AudioEvent::Ended)) => None,
+                // Files without audio, or the audio worker stopped.
+                Some(Err(_)) => {
+                    self.audio_events = channel::never();
+                    None
+                }
+                None => None,
+            };
+            if let Some(event) = event {
+                self.events.send(event).ok();
+            }
+
+            // Seeking away from the end clears the flags.
+            let audio_ended = match self.audio.as_ref().and_then(Weak::upgrade) {
+                Some(audio) => audio.is_ended(),
+                None => true,
+            };
+            let video_ended = match &self.video_ended {
+                Some(ended) => ended.load(Ordering::Relaxed),
+                None => true,
+            };
+            let ended = audio_ended && video_ended;
+            if !ended {
+                completed = false;
+            } else if !completed {
+                completed = true;
+                self.events.send(PlayerEvent::Completed).ok();
+                self.state.advance(
+                    &[
+                        PlayerState::Paused,
+                        PlayerState::Playing,
+                        PlayerState::Buffering,
+                    ],
+                    PlayerState::Ended,
+                );
+            }
+
+            if self.state.get() == PlayerState::Playing
+                && last_position.elapsed() >= POSITION_INTERVAL
+            {
+                last_position = Instant::now();
+                let audio = self.audio.as_ref().and_then(Weak::upgrade);
+                if let Some(audio) = audio {
+                    self.events
+                        .send(PlayerEvent::Position(audio.position()))
+                        .ok();
+                }
+            }
+        }
+    }
+}
+
+/// Reads packets ahead into the queue of their stream.
+struct Demuxer {
+    context: PlaybackContext,
+    /// Queues by stream index.
+    queues: Vec<(isize, SharedQueue)>,
+    read_ahead: ReadAhead,
+    seeks: Receiver<Seek>,
+    /// Disconnects once the `Player` is dropped.
+    cancelled: Receiver<()>,
+    state: Arc<StateMachine>,
+}
+
+impl Demuxer {
+    /// Runs until the player is dropped or every decoder stopped, waits for
+    /// seeks at the end of the file.
+    fn run(mut self) {
+        while !self.queues.iter().all(|(_, q)| q.is_closed()) {
+            if let Err(TryRecvError::Disconnected) = self.cancelled.try_recv() {
+                break;
+            }
+            // Only the newest of several queued seeks matters.
+            if let Some(seek) = self.seeks.try_iter().last() {
+                if !self.seek(seek) {
+                    break;
+                }
+                continue;
+            }
+            if self.context.is_eof() {
+                for (_, queue) in &self.queues {
+                    queue.finish();
+                }
+                let seek = select! {
+                    recv(self.seeks) -> seek => seek,
+                    recv(self.cancelled) -> _ => break,
+                };
+                match seek {
+                    Ok(seek) => {
+                        let seek = self.seeks.try_iter().last().unwrap_or(seek);
+                        if !self.seek(seek) {
                             break;
                         }
                     }
-                    MediaKind::Audio(_) => {
-                        if a_s.send(frame).is_err() {
-                            break;
-                        }
+                    Err(_) => break,
+                }
+                continue;
+            }
+            if !self.needs_packets() {
+                thread::sleep(DEMUXER_POLL_INTERVAL);
+                continue;
+            }
+            match self.context.read_packet() {
+                Ok(Some(packet)) => {
+                    let index = packet.stream_index;
+                    if let Some((_, queue)) = self.queues.iter().find(|(i, _)| *i == index) {
+                        queue.push(packet);
                     }
                 }
+                Ok(None) => {}
+                Err(err) => {
+                    log::error!("failed to read packet: {}", err);
+                    self.state.fail(err.to_string());
+                    break;
+                }
+            }
+        }
+        for (_, queue) in &self.queues {
+            queue.finish();
+        }
+    }
+
+    /// Restarts demuxing at `seek` and flushes the queues, returns `false`
+    /// if the file can't be read anymore.
+    fn seek(&mut self, seek: Seek) -> bool {
+        // The first seek indexes the file, disposing must not wait for that.
+        let cancelled = &self.cancelled;
+        let indexed = self
+            .context
+            .index_keyframes(|| matches!(cancelled.try_recv(), Err(TryRecvError::Disconnected)));
+        if !indexed {
+            return false;
+        }
+        match self.context.seek(seek.target.time()) {
+            Ok(()) => {
+                for (_, queue) in &self.queues {
+                    queue.flush(seek);
+                }
+                true
+            }
+            Err(err) => {
+                log::error!("failed to seek: {}", err);
+                self.state.fail(err.to_string());
+                false
+            }
+        }
+    }
+
+    /// Whether to demux further, stops once the queues hold `read_ahead`
+    /// bytes or every stream has `read_ahead` queued.
+    fn needs_packets(&self) -> bool {
+        let open = || {
+            self.queues
+                .iter()
+                .map(|(_, q)| q)
+                .filter(|q| !q.is_closed())
+        };
+        let bytes: usize = open().map(SharedQueue::bytes).sum();
+        let filled = open().all(|q| q.duration() >= self.read_ahead.duration);
+        bytes < self.read_ahead.bytes && !filled
+    }
+}
+
+/// Decodes the packets of one stream on its own thread, so a slow stream
+/// doesn't delay the others.
+struct StreamDecoder {
+    decoder: DecContext,
+    queue: SharedQueue,
+    frames: SyncSender<Decoded>,
+    /// Disconnects once the `Player` is dropped.
+    cancelled: Receiver<()>,
+}
+
+impl StreamDecoder {
+    /// Runs until the player is dropped or the receiver of `frames` hung up.
+    fn run(mut self) {
+        let mut ended = false;
+        loop {
+            if let Err(TryRecvError::Disconnected) = self.cancelled.try_recv() {
+                break;
+            }
+            if !ended && self.queue.is_finished() {
+                ended = true;
+                if self.frames.send(Decoded::End).is_err() {
+                    break;
+                }
             }
+            let decoded = match self.queue.pop(DECODER_POLL_INTERVAL) {
+                Some(Queued::Packet(packet)) => {
+                    if let Err(err) = self.decoder.send_packet(&packet) {
+                        log::warn!("failed to decode packet: {}", err);
+                        continue;
+                    }
+                    match self.decoder.receive_frame() {
+                        Ok(frame) => Decoded::Frame(frame),
+                        Err(_) => continue,
+                    }
+                }
+                Some(Queued::Flush(seek)) => {
+                    ended = false;
+                    Decoded::Flush(seek)
+                }
+                None => continue,
+            };
+            if self.frames.send(decoded).is_err() {
+                break;
+            }
+        }
+        self.queue.close();
+    }
+}
+
+/// Frame sink of players without one.
+struct DiscardFrames;
+
+impl FrameSink for DiscardFrames {
+    fn post_frame(&mut self, _frame: RgbaImage, _pts: Duration) {}
+}
+
+impl Player {
+    /// Returns a builder for a player.
+    pub fn builder() -> PlayerBuilder {
+        PlayerBuilder {
+            path: None,
+            frame_sink: None,
+            audio_sink: AudioSink::default(),
+            audio_device: None,
+            effects: Vec::new(),
+            volume: 1.0,
+            muted: false,
+            speed: 1.0,
+            read_ahead: ReadAhead::default(),
+            video_threads: 0,
+        }
+    }
+
+    fn new(
+        path: &Path,
+        frame_sink: Box<dyn FrameSink>,
+        audio_sink: &AudioSink,
+        audio_device: Option<&str>,
+        effects: Vec<Box<dyn AudioEffect>>,
+        read_ahead: ReadAhead,
+        video_threads: usize,
+    ) -> Result<Self, PlayerError> {
+        let mut context = PlaybackContext::with_video_threads(path, video_threads)?;
+        let (v_s, v_r) = mpsc::sync_channel(VIDEO_FRAMES);
+        let (a_s, a_r) = mpsc::sync_channel(AUDIO_FRAMES);
+        let (audio_events, audio_events_rx) = channel::unbounded();
+        let (event_sender, events) = channel::unbounded();
+
+        let audio_stream = match context.audio.take() {
+            Some(info) => {
+                let audio = AudioPlayer::new(&info, audio_sink, audio_device)?;
+                Some(Arc::new(audio.create_stream(a_r, audio_events, effects)?))
+            }
+            None => None,
+        };
+
+        // Receives once the first frame was presented.
+        let (preroll, prerolled) = channel::bounded(1);
+        let video_info = context.video.take();
+        let video_stream = video_info.as_ref().map(|info| {
+            let video = VideoPlayer::new(info, frame_sink);
+            video.create_stream(v_r, event_sender.clone(), preroll)
         });
 
+        let (width, height) = video_info
+            .map(|info| (info.width as _, info.height as _))
+            .unwrap_or_default();
+        let initialized = vec![
+            PlayerEvent::Initialized {
+                width,
+                height,
+                duration: context.duration(),
+            },
+            PlayerEvent::TracksChanged {
+                video: context.video_index(),
+                audio: context.audio_index(),
+            },
+        ];
+
+        // decoder tasks
+        let state = Arc::new(StateMachine::new(event_sender.clone()));
+        let (cancel, cancelled) = channel::bounded(0);
+        let (seeks, seeks_rx) = channel::unbounded();
+        let mut threads = Vec::with_capacity(4);
+        let mut queues = Vec::with_capacity(2);
+        let streams = vec![(context.video_index, v_s), (context.audio_index, a_s)];
+        for (index, frames) in streams {
+            let index = match index {
+                Some(index) => index,
+                None => continue,
+            };
+            let decoder = match context.decoders.remove(&index) {
+                Some(decoder) => decoder,
+                None => continue,
+            };
+            let queue = SharedQueue::new();
+            queues.push((index, queue.clone()));
+            let decoder = StreamDecoder {
+                decoder,
+                queue,
+                frames,
+                cancelled: cancelled.clone(),
+            };
+            threads.push(thread::spawn(move || decoder.run()));
+        }
+        let demuxer = Demuxer {
+            context,
+            queues,
+            read_ahead,
+            seeks: seeks_rx,
+            cancelled: cancelled.clone(),
+            state: state.clone(),
+        };
+        threads.push(thread::spawn(move || demuxer.run()));
+
+        let pump = EventPump {
+            audio_events: audio_events_rx,
+            audio: audio_stream.as_ref().map(Arc::downgrade),
+            video_ended: video_stream.as_ref().map(VideoStream::ended),
+            state: state.clone(),
+            cancel: cancelled,
+            prerolled,
+            initialized,
+            events: event_sender,
+        };
+        threads.push(thread::spawn(move || pump.run()));
+
         Ok(Self {
-            audio: Some(audio_stream),
-            video: Some(video_stream),
-            width: video_info.width as _,
-            height: video_info.height as _,
+            path: path.to_path_buf(),
+            audio: audio_stream,
+            video: video_stream,
+            events,
+            state,
+            cancel: Some(cancel),
+            seeks,
+            seek_generation: AtomicU64::new(0),
+            stepped: AtomicBool::new(false),
+            threads,
+            loudness: Mutex::new(None),
+            width,
+            height,
         })
     }
 
+    /// The file being played.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Width of the video in pixels, 0 without video.
     pub fn width(&self) -> i64 {
         self.width
     }
 
+    /// Height of the video in pixels, 0 without video.
     pub fn height(&self) -> i64 {
         self.height
     }
 
+    /// Returns a receiver for the events emitted during playback.
+    ///
+    /// Receivers share the events, each event is received by only one of
+    /// them.
+    pub fn events(&self) -> Receiver<PlayerEvent> {
+        self.events.clone()
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> PlayerState {
+        self.state.get()
+    }
+
+    /// Starts or resumes playback.
     pub fn play(&self) -> Result<(), PlayerError> {
+        if let PlayerState::Playing | PlayerState::Buffering = self.state.get() {
+            return Ok(());
+        }
+        self.state.check_change("play", PlayerState::Playing)?;
+        // Bring the audio to the frame stepped to.
+        if self.stepped.swap(false, Ordering::Relaxed) {
+            if let Some(video) = &self.video {
+                self.seek(SeekTarget::At(video.position()));
+            }
+        }
         if let Some(audio) = &self.audio {
             audio.play()?;
         }
         if let Some(video) = &self.video {
             video.play();
         }
-        Ok(())
+        self.state.change("play", PlayerState::Playing)
     }
 
+    /// Pauses playback after a short fade out.
     pub fn pause(&self) -> Result<(), PlayerError> {
+        if let PlayerState::Paused | PlayerState::Ended = self.state.get() {
+            return Ok(());
+        }
+        self.state.check_change("pause", PlayerState::Paused)?;
+        self.pause_streams()?;
+        self.state.change("pause", PlayerState::Paused)
+    }
+
+    fn pause_streams(&self) -> Result<(), PlayerError> {
         if let Some(audio) = &self.audio {
             audio.pause()?;
         }
@@ -216,17 +1247,457 @@ impl Player {
         Ok(())
     }
 
+    /// Stops playback for good, every later call that changes the playback
+    /// fails.
+    pub fn dispose(&self) {
+        if self.state.get() == PlayerState::Disposed {
+            return;
+        }
+        if let Some(audio) = &self.audio {
+            if let Err(err) = audio.pause() {
+                log::warn!("failed to stop audio: {}", err);
+            }
+        }
+        if let Some(video) = &self.video {
+            video.pause();
+        }
+        self.state.change("dispose", PlayerState::Disposed).ok();
+    }
+
+    /// Playback position in milliseconds, the position of the frame shown
+    /// after stepping.
     pub fn position(&self) -> i64 {
-        0
+        let audio = self.audio.as_ref().map(|stream| stream.position());
+        let video = self.video.as_ref().map(VideoStream::position);
+        let position = if self.stepped.load(Ordering::Relaxed) {
+            video.or(audio)
+        } else {
+            audio.or(video)
+        };
+        position.unwrap_or_default().as_millis() as i64
     }
 
-    pub fn seek_to(&self, _location: i64) {}
+    /// Seeks to `location` in milliseconds, playback continues at the first
+    /// frame at or after it.
+    pub fn seek_to(&self, location: i64) -> Result<(), PlayerError> {
+        self.state.check_usable("seek")?;
+        self.stepped.store(false, Ordering::Relaxed);
+        self.seek(SeekTarget::At(
+            Duration::from_millis(location.max(0) as u64),
+        ));
+        self.leave_end()
+    }
+
+    /// Presents the next video frame while paused.
+    pub fn step_forward(&self) -> Result<(), PlayerError> {
+        let video = self.stepping_video("step forward", &[PlayerState::Paused])?;
+        video.step();
+        self.stepped.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Presents the previous video frame while paused or at the end.
+    pub fn step_backward(&self) -> Result<(), PlayerError> {
+        let states = [PlayerState::Paused, PlayerState::Ended];
+        let video = self.stepping_video("step backward", &states)?;
+        self.stepped.store(true, Ordering::Relaxed);
+        self.seek(SeekTarget::Before(video.position()));
+        self.leave_end()
+    }
+
+    /// Returns the frame presented last, which can be encoded on another
+    /// thread.
+    pub fn current_frame(&self) -> Result<VideoFrame, PlayerError> {
+        self.state.check_usable("capture a frame")?;
+        let video = self.video.as_ref().ok_or(PlayerError::NoVideo)?;
+        let frame = video.current_frame().ok_or(PlayerError::NoFrame)?;
+        Ok(VideoFrame::new(frame))
+    }
 
+    /// Encodes the frame presented last as `format`, scaled down to fit into
+    /// `max_size` pixels if given.
+    pub fn capture_frame(
+        &self,
+        format: ImageFormat,
+        max_size: Option<u32>,
+    ) -> Result<Vec<u8>, PlayerError> {
+        self.current_frame()?.encode(format, max_size)
+    }
+
+    fn stepping_video(
+        &self,
+        action: &'static str,
+        states: &[PlayerState],
+    ) -> Result<&VideoStream, PlayerError> {
+        let state = self.state.get();
+        if !states.contains(&state) {
+            return Err(PlayerError::InvalidState { action, state });
+        }
+        self.video.as_ref().ok_or(PlayerError::NoVideo)
+    }
+
+    /// Restarts the decoders at `target`.
+    fn seek(&self, target: SeekTarget) {
+        let generation = self.seek_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(video) = &self.video {
+            video.seek(generation);
+        }
+        self.seeks.send(Seek { target, generation }).ok();
+    }
+
+    /// Pauses after seeking away from the end.
+    fn leave_end(&self) -> Result<(), PlayerError> {
+        if self.state.get() == PlayerState::Ended {
+            self.pause_streams()?;
+            self.state.change("seek", PlayerState::Paused)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the linear volume, changes are ramped to avoid clicks.
     pub fn set_volume(&self, volume: f64) {
         if let Some(stream) = &self.audio {
             stream.set_volume(volume);
         }
     }
 
+    /// Reads the container and track metadata of the file.
+    pub fn metadata(&self) -> Result<Metadata, PlayerError> {
+        Ok(metadata::read_metadata(&self.path)?)
+    }
+
+    /// Returns how often the audio output ran out of samples.
+    pub fn audio_stats(&self) -> AudioStats {
+        self.audio
+            .as_ref()
+            .map(|stream| stream.stats())
+            .unwrap_or_default()
+    }
+
+    /// Changes the playback speed while keeping the pitch, `speed` must be
+    /// between `MIN_SPEED` and `MAX_SPEED`.
+    pub fn set_playback_speed(&self, speed: f64) -> Result<(), PlayerError> {
+        if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+            return Err(PlayerError::InvalidSpeed(speed));
+        }
+        self.state.check_usable("change the playback speed")?;
+        if let Some(stream) = &self.audio {
+            stream.set_speed(speed);
+        }
+        if let Some(stream) = &self.video {
+            stream.set_speed(speed);
+        }
+        Ok(())
+    }
+
+    /// Pans the audio from `-1.0` (left) to `1.0` (right).
+    pub fn set_balance(&self, balance: f64) {
+        if let Some(stream) = &self.audio {
+            stream.set_balance(balance as f32);
+        }
+    }
+
+    /// Adjusts the volume to a common loudness, using the gain from the
+    /// track's tags or measuring it in the background on first use.
+    pub fn set_loudness_normalization(&self, enabled: bool) {
+        let stream = match &self.audio {
+            Some(stream) => stream,
+            None => return,
+        };
+        stream.set_normalization(enabled);
+        let mut loudness = self.loudness.lock().unwrap();
+        if !enabled || loudness.is_some() {
+            return;
+        }
+        let path = self.path.clone();
+        let stream = Arc::downgrade(stream);
+        *loudness = Some(thread::spawn(move || {
+            let cancelled = || stream.upgrade().is_none();
+            match normalization_gain(&path, cancelled) {
+                Ok(Some(gain)) => {
+                    log::debug!("loudness normalization gain {:.2} dB", gain);
+                    if let Some(stream) = stream.upgrade() {
+                        stream.set_normalization_gain(gain);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => log::warn!("failed to measure loudness: {}", err),
+            }
+        }));
+    }
+
+    /// Enables level and spectrum events, sent every `interval` while playing.
+    pub fn set_metering(&self, enabled: bool, interval: Duration) {
+        if let Some(stream) = &self.audio {
+            stream.set_metering(enabled, interval);
+        }
+    }
+
+    /// Replaces the equalizer bands, an empty list disables the equalizer.
+    pub fn set_equalizer_bands(&self, bands: Vec<EqualizerBand>) {
+        if let Some(stream) = &self.audio {
+            stream.set_equalizer_bands(bands);
+        }
+    }
+
+    /// Lowers the volume while another player holds the audio focus.
+    pub fn set_ducked(&self, ducked: bool) {
+        if let Some(stream) = &self.audio {
+            stream.set_ducked(ducked);
+        }
+    }
+
+    /// Mutes the audio without changing the volume.
+    pub fn set_muted(&self, muted: bool) {
+        if let Some(stream) = &self.audio {
+            stream.set_muted(muted);
+        }
+    }
+
+    /// Sets how long volume and mute changes take.
+    pub fn set_volume_ramp(&self, duration: Duration) {
+        if let Some(stream) = &self.audio {
+            stream.set_ramp_duration(duration);
+        }
+    }
+
+    /// Moves the audio to another output device, `None` follows the default
+    /// device.
+    pub fn set_audio_device(&self, device: Option<&str>) -> Result<(), PlayerError> {
+        self.state.check_usable("change the audio device")?;
+        if let Some(stream) = &self.audio {
+            stream.set_device(device)?;
+        }
+        Ok(())
+    }
+
+    /// Restarts playback at the end, not implemented yet.
     pub fn set_looping(&self, _looping: bool) {}
 }
+
+impl Drop for Player {
+    /// Stops every thread of the player and waits for them, which closes the
+    /// file, the audio output and the frame sink and disconnects `events`.
+    fn drop(&mut self) {
+        self.cancel.take();
+        self.video.take();
+        // Also cancels the loudness measurement.
+        self.audio.take();
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+        if let Some(thread) = self.loudness.get_mut().unwrap().take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerState::{Buffering, Disposed, Ended, Initializing, Paused, Playing};
+    use super::*;
+
+    const STATES: [PlayerState; 7] = [
+        Initializing,
+        Paused,
+        Playing,
+        Buffering,
+        Ended,
+        PlayerState::Error,
+        Disposed,
+    ];
+
+    #[test]
+    fn allowed_state_changes() {
+        let allowed = [
+            (Initializing, PlayerState::Error),
+            (Initializing, Disposed),
+            (Paused, Playing),
+            (Paused, Ended),
+            (Paused, PlayerState::Error),
+            (Paused, Disposed),
+            (Playing, Paused),
+            (Playing, Buffering),
+            (Playing, Ended),
+            (Playing, PlayerState::Error),
+            (Playing, Disposed),
+            (Buffering, Paused),
+            (Buffering, Playing),
+            (Buffering, Ended),
+            (Buffering, PlayerState::Error),
+            (Buffering, Disposed),
+            (Ended, Paused),
+            (Ended, PlayerState::Error),
+            (Ended, Disposed),
+            (PlayerState::Error, Disposed),
+        ];
+        for &from in &STATES {
+            for &to in &STATES {
+                assert_eq!(
+                    from.can_change_to(to),
+                    allowed.contains(&(from, to)),
+                    "{} -> {}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn play_after_dispose_fails() {
+        let (events, received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        state.change("dispose", Disposed).unwrap();
+        match state.change("play", Playing) {
+            Err(PlayerError::InvalidState { action, state }) => {
+                assert_eq!((action, state), ("play", Disposed));
+            }
+            result => panic!("play after dispose returned {:?}", result),
+        }
+        assert!(state.check_usable("seek").is_err());
+        assert_eq!(state.get(), Disposed);
+        let changes: Vec<_> = received.try_iter().collect();
+        assert_eq!(changes, vec![PlayerEvent::StateChanged(Disposed)]);
+    }
+
+    #[test]
+    fn play_from_ended_needs_a_seek() {
+        let (events, _received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        for &to in &[Paused, Playing, Ended] {
+            state.change("advance", to).unwrap();
+        }
+        assert!(state.check_change("play", Playing).is_err());
+        assert_eq!(state.get(), Ended);
+        state.change("seek", Paused).unwrap();
+        state.change("play", Playing).unwrap();
+    }
+
+    #[test]
+    fn initialize_only_while_initializing() {
+        let (events, received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        state.initialize(vec![PlayerEvent::Completed]);
+        assert_eq!(state.get(), Paused);
+        let sent: Vec<_> = received.try_iter().collect();
+        assert_eq!(
+            sent,
+            vec![PlayerEvent::Completed, PlayerEvent::StateChanged(Paused)]
+        );
+
+        let (events, received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        state.change("dispose", Disposed).unwrap();
+        state.initialize(vec![PlayerEvent::Completed]);
+        assert_eq!(state.get(), Disposed);
+        let sent: Vec<_> = received.try_iter().collect();
+        assert_eq!(sent, vec![PlayerEvent::StateChanged(Disposed)]);
+    }
+
+    fn event_pump(presented: bool) -> (EventPump, Receiver<PlayerEvent>, Sender<()>) {
+        let (events, received) = channel::unbounded();
+        let (cancel, cancelled) = channel::bounded(0);
+        let (preroll, prerolled) = channel::bounded(1);
+        if presented {
+            preroll.send(()).unwrap();
+        }
+        let pump = EventPump {
+            audio_events: channel::never(),
+            audio: None,
+            video_ended: Some(Arc::new(AtomicBool::new(false))),
+            state: Arc::new(StateMachine::new(events.clone())),
+            cancel: cancelled,
+            prerolled,
+            initialized: vec![PlayerEvent::TracksChanged {
+                video: Some(0),
+                audio: None,
+            }],
+            events,
+        };
+        (pump, received, cancel)
+    }
+
+    #[test]
+    fn pump_reports_initialized_after_first_frame() {
+        let (pump, received, cancel) = event_pump(true);
+        let state = pump.state.clone();
+        let thread = thread::spawn(move || pump.run());
+        let first = received.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(matches!(first, PlayerEvent::TracksChanged { .. }));
+        drop(cancel);
+        thread.join().unwrap();
+        assert_eq!(state.get(), Paused);
+    }
+
+    #[test]
+    fn pump_fails_without_video_frame() {
+        let (pump, received, _cancel) = event_pump(false);
+        let state = pump.state.clone();
+        pump.run();
+        assert_eq!(state.get(), PlayerState::Error);
+        let sent: Vec<_> = received.try_iter().collect();
+        assert_eq!(
+            sent,
+            vec![
+                PlayerEvent::Error("no video frame could be decoded".to_string()),
+                PlayerEvent::StateChanged(PlayerState::Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_bytes_leaves_out_the_gap() {
+        let data: Vec<u8> = (0..20).collect();
+        let mut reader = SkipBytes {
+            inner: io::Cursor::new(data),
+            start: 5,
+            len: 10,
+            position: 0,
+        };
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, [0, 1, 2, 3, 4, 15, 16, 17, 18, 19]);
+        let mut byte = [0];
+        assert_eq!(io::Seek::seek(&mut reader, SeekFrom::Start(3)).unwrap(), 3);
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [3]);
+        assert_eq!(
+            io::Seek::seek(&mut reader, SeekFrom::Current(3)).unwrap(),
+            7
+        );
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [17]);
+        assert_eq!(io::Seek::seek(&mut reader, SeekFrom::End(-1)).unwrap(), 9);
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [19]);
+        assert!(io::Seek::seek(&mut reader, SeekFrom::Current(-20)).is_err());
+    }
+
+    #[test]
+    fn advance_only_from_listed_states() {
+        let (events, _received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        state.advance(&[Playing], Ended);
+        assert_eq!(state.get(), Initializing);
+        state.initialize(Vec::new());
+        state.advance(&[Paused], Ended);
+        assert_eq!(state.get(), Ended);
+    }
+
+    #[test]
+    fn pause_while_initializing_fails() {
+        let (events, received) = channel::unbounded();
+        let state = StateMachine::new(events);
+        assert!(state.check_change("pause", Paused).is_err());
+        assert!(state.change("pause", Paused).is_err());
+        state.initialize(vec![PlayerEvent::Initialized {
+            width: 64,
+            height: 36,
+            duration: Some(Duration::from_secs(2)),
+        }]);
+        assert_eq!(state.get(), Paused);
+        let sent: Vec<_> = received.try_iter().collect();
+        assert!(matches!(sent[0], PlayerEvent::Initialized { .. }));
+    }
+}
@@ -1,8 +1,20 @@
-use crate::player::Player;
+use crate::audio;
+use crate::dsp::{AudioEffect, EqualizerBand};
+use crate::event::PlayerEvent;
+use crate::focus::{AudioFocus, FocusPolicy};
+use crate::metadata::{Metadata, TrackKind};
+use crate::meter;
+use crate::player::{Player, PlayerError};
+use crate::sink::AudioSink;
+use crate::snapshot;
 use crate::types::*;
+use crossbeam::channel::{self, select, Sender};
 use flutter_plugins::prelude::*;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, UNIX_EPOCH};
 
 const PLUGIN_NAME: &str = module_path!();
 const CHANNEL_NAME: &str = "flutter.io/videoPlayer";
@@ -24,14 +36,133 @@ impl From<InvalidTextureId> for MethodCallError {
     }
 }
 
+/// Players that couldn't move to another audio device, by texture id.
+#[derive(Debug)]
+struct AudioDeviceError(Vec<(i64, PlayerError)>);
+
+impl std::fmt::Display for AudioDeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "failed to change the audio device of")?;
+        for (i, (texture_id, error)) in self.0.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(f, "{} player {} ({})", separator, texture_id, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AudioDeviceError {}
+
+impl From<AudioDeviceError> for MethodCallError {
+    fn from(error: AudioDeviceError) -> Self {
+        MethodCallError::from_error(error)
+    }
+}
+
 #[derive(Default)]
 pub struct VideoPlugin {
     handler: Arc<RwLock<Handler>>,
 }
 
+impl VideoPlugin {
+    /// Sets where players created afterwards send their audio, e.g. a null
+    /// sink on machines without an audio device.
+    pub fn with_audio_sink(self, sink: AudioSink) -> Self {
+        self.handler.write().unwrap().audio_sink = sink;
+        self
+    }
+
+    /// Sets how many threads players created afterwards decode video with,
+    /// 0 lets the decoder choose.
+    pub fn with_video_decoder_threads(self, threads: usize) -> Self {
+        self.handler.write().unwrap().video_threads = threads;
+        self
+    }
+
+    /// Adds an effect to the audio chain of every player created afterwards.
+    ///
+    /// `factory` is called once per player, effects run after the equalizer
+    /// and before the limiter in the order they were added.
+    pub fn with_audio_effect<F>(self, factory: F) -> Self
+    where
+        F: Fn() -> Box<dyn AudioEffect> + Send + Sync + 'static,
+    {
+        self.handler
+            .write()
+            .unwrap()
+            .effects
+            .push(Box::new(factory));
+        self
+    }
+}
+
+type EffectFactory = Box<dyn Fn() -> Box<dyn AudioEffect> + Send + Sync>;
+
 #[derive(Default)]
 struct Handler {
     streams: HashMap<i64, Arc<RwLock<StreamHandler>>>,
+    audio_sink: AudioSink,
+    /// Output device for new players, `None` selects the default device.
+    audio_device: Option<String>,
+    focus: Arc<Mutex<Focus>>,
+    effects: Vec<EffectFactory>,
+    video_threads: usize,
+}
+
+impl Handler {
+    fn update_ducking(&self) {
+        self.focus.lock().unwrap().update_ducking();
+    }
+
+    /// Stops the player of `texture_id` and releases its texture and event
+    /// channel.
+    fn dispose(&mut self, texture_id: i64, engine: &FlutterEngine) -> Result<(), InvalidTextureId> {
+        let stream = self.streams.remove(&texture_id).ok_or(InvalidTextureId)?;
+        // The forwarding thread only holds the stream while the focus is
+        // locked, so it can't end up dropping it.
+        self.focus.lock().unwrap().remove(texture_id);
+        {
+            let stream = stream.read().unwrap();
+            stream.player.dispose();
+            engine.remove_channel(&stream.channel);
+        }
+        // Joins the threads of the player and the forwarding thread,
+        // dropping its texture unregisters it from the engine.
+        drop(stream);
+        Ok(())
+    }
+}
+
+/// Audio focus of all players, shared with the threads forwarding their
+/// events so players that complete or fail stop ducking the others.
+#[derive(Default)]
+struct Focus {
+    policies: AudioFocus,
+    players: HashMap<i64, Weak<RwLock<StreamHandler>>>,
+}
+
+impl Focus {
+    /// Ducks or restores every player according to the players currently
+    /// holding focus.
+    fn update_ducking(&self) {
+        for (id, stream) in &self.players {
+            if let Some(stream) = stream.upgrade() {
+                let ducked = self.policies.is_ducked(*id);
+                stream.read().unwrap().player.set_ducked(ducked);
+            }
+        }
+    }
+
+    /// Records that a player stopped playing by itself.
+    fn on_stop(&mut self, texture_id: i64) {
+        self.policies.on_stop(texture_id);
+        self.update_ducking();
+    }
+
+    fn remove(&mut self, texture_id: i64) {
+        self.policies.remove(texture_id);
+        self.players.remove(&texture_id);
+    }
 }
 
 impl Plugin for VideoPlugin {
@@ -52,7 +183,15 @@ impl MethodCallHandler for Handler {
         engine: FlutterEngine,
     ) -> Result<Value, MethodCallError> {
         match call.method.as_str() {
-            "init" => Ok(Value::Null),
+            // Called on hot restart, players of the previous run are gone on
+            // the Dart side.
+            "init" => {
+                let ids: Vec<i64> = self.streams.keys().copied().collect();
+                for id in ids {
+                    self.dispose(id, &engine)?;
+                }
+                Ok(Value::Null)
+            }
             "create" => {
                 let args: CreateArgs = from_value(&call.args)?;
 
@@ -62,17 +201,32 @@ impl MethodCallHandler for Handler {
 
                 // create player
                 let player = if let Some(asset) = args.asset.as_ref() {
-                    let path = engine.assets().join(asset);
-                    Player::from_path(&path, texture)?
+                    let mut builder = Player::builder()
+                        .path(engine.assets().join(asset))
+                        .frame_sink(texture)
+                        .audio_sink(self.audio_sink.clone())
+                        .video_decoder_threads(self.video_threads);
+                    if let Some(device) = &self.audio_device {
+                        builder = builder.audio_device(device.as_str());
+                    }
+                    for factory in &self.effects {
+                        builder = builder.audio_effect(factory());
+                    }
+                    builder.build()?
                 } else {
                     unimplemented!();
                 };
 
                 // register channel
                 let channel = format!("{}/videoEvents{}", CHANNEL_NAME, texture_id);
-                let handler = Arc::new(RwLock::new(StreamHandler::new(channel.clone(), player)));
+                let handler = StreamHandler::new(channel.clone(), texture_id, player, &self.focus);
+                let handler = Arc::new(RwLock::new(handler));
                 let stream_handler = Arc::downgrade(&handler);
                 self.streams.insert(texture_id, handler);
+                let mut focus = self.focus.lock().unwrap();
+                focus.players.insert(texture_id, stream_handler.clone());
+                focus.update_ducking();
+                drop(focus);
                 engine.with_channel_registrar(PLUGIN_NAME, |registrar| {
                     registrar.register_channel(EventChannel::new(channel, stream_handler));
                 });
@@ -91,16 +245,146 @@ impl MethodCallHandler for Handler {
                 stream.read().unwrap().player.set_volume(args.volume);
                 Ok(Value::Null)
             }
+            "setBalance" => {
+                let args: SetBalanceArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream.read().unwrap().player.set_balance(args.balance);
+                Ok(Value::Null)
+            }
+            "setLoudnessNormalization" => {
+                let args: SetLoudnessNormalizationArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream
+                    .read()
+                    .unwrap()
+                    .player
+                    .set_loudness_normalization(args.enabled);
+                Ok(Value::Null)
+            }
+            "setEqualizerBands" => {
+                let args: SetEqualizerBandsArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let bands = args
+                    .bands
+                    .into_iter()
+                    .map(|band| EqualizerBand {
+                        kind: band.kind,
+                        frequency: band.frequency,
+                        gain: band.gain,
+                        q: band.q,
+                    })
+                    .collect();
+                stream.read().unwrap().player.set_equalizer_bands(bands);
+                Ok(Value::Null)
+            }
+            "setMuted" => {
+                let args: SetMutedArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream.read().unwrap().player.set_muted(args.muted);
+                Ok(Value::Null)
+            }
+            "setVolumeRamp" => {
+                let args: SetVolumeRampArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let duration = Duration::from_millis(args.duration.max(0) as u64);
+                stream.read().unwrap().player.set_volume_ramp(duration);
+                Ok(Value::Null)
+            }
+            "setMeteringEnabled" => {
+                let args: SetMeteringEnabledArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let interval = args
+                    .interval
+                    .map(|interval| interval.max(1) as u64)
+                    .unwrap_or(meter::DEFAULT_INTERVAL_MS);
+                stream
+                    .read()
+                    .unwrap()
+                    .player
+                    .set_metering(args.enabled, Duration::from_millis(interval));
+                Ok(Value::Null)
+            }
+            "setPlaybackSpeed" => {
+                let args: SetPlaybackSpeedArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream
+                    .read()
+                    .unwrap()
+                    .player
+                    .set_playback_speed(args.speed)?;
+                Ok(Value::Null)
+            }
             "pause" => {
                 let args: TextureIdArgs = from_value(&call.args)?;
                 let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
                 stream.read().unwrap().player.pause()?;
+                self.focus.lock().unwrap().on_stop(args.texture_id);
                 Ok(Value::Null)
             }
             "play" => {
                 let args: TextureIdArgs = from_value(&call.args)?;
                 let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let others: Vec<i64> = {
+                    let mut focus = self.focus.lock().unwrap();
+                    if focus.policies.on_play(args.texture_id) == FocusPolicy::Exclusive {
+                        let ids = focus.players.keys().copied();
+                        ids.filter(|id| *id != args.texture_id).collect()
+                    } else {
+                        Vec::new()
+                    }
+                };
+                // Pausing fades out the audio, so it happens without holding
+                // the focus lock the event forwarding threads wait for.
+                let paused: Vec<i64> = others
+                    .into_iter()
+                    .filter(|id| {
+                        let other = match self.streams.get(id) {
+                            Some(other) => other,
+                            None => return false,
+                        };
+                        // Pausing sends a stateChanged event to Dart. Players
+                        // still initializing or failed aren't playing anyway.
+                        match other.read().unwrap().player.pause() {
+                            Ok(()) => true,
+                            Err(err) => {
+                                log::debug!("not pausing player {}: {}", id, err);
+                                false
+                            }
+                        }
+                    })
+                    .collect();
+                if !paused.is_empty() {
+                    let mut focus = self.focus.lock().unwrap();
+                    for id in paused {
+                        focus.policies.on_stop(id);
+                    }
+                }
                 stream.read().unwrap().player.play()?;
+                self.update_ducking();
+                Ok(Value::Null)
+            }
+            "setMixWithOthers" => {
+                let args: MixWithOthersArgs = from_value(&call.args)?;
+                self.focus
+                    .lock()
+                    .unwrap()
+                    .policies
+                    .set_default(if args.mix_with_others {
+                        FocusPolicy::Mix
+                    } else {
+                        FocusPolicy::Exclusive
+                    });
+                Ok(Value::Null)
+            }
+            "setAudioFocusPolicy" => {
+                let args: SetAudioFocusPolicyArgs = from_value(&call.args)?;
+                if let Some(texture_id) = args.texture_id {
+                    self.streams.get(&texture_id).ok_or(InvalidTextureId)?;
+                    let policies = &mut self.focus.lock().unwrap().policies;
+                    policies.set_policy(texture_id, args.policy);
+                } else {
+                    self.focus.lock().unwrap().policies.set_default(args.policy);
+                }
                 Ok(Value::Null)
             }
             "position" => {
@@ -109,17 +393,108 @@ impl MethodCallHandler for Handler {
                 let position = stream.read().unwrap().player.position();
                 Ok(Value::I64(position))
             }
+            "listAudioDevices" => {
+                let devices: Vec<AudioDeviceResult> = audio::output_devices()
+                    .map_err(PlayerError::from)?
+                    .into_iter()
+                    .map(|device| AudioDeviceResult {
+                        name: device.name,
+                        is_default: device.is_default,
+                    })
+                    .collect();
+                Ok(to_value(devices)?)
+            }
+            "setAudioDevice" => {
+                let args: SetAudioDeviceArgs = from_value(&call.args)?;
+                let device = args.device.as_deref();
+                if let Some(texture_id) = args.texture_id {
+                    let stream = self.streams.get(&texture_id).ok_or(InvalidTextureId)?;
+                    stream.read().unwrap().player.set_audio_device(device)?;
+                } else {
+                    // Moves as many players as possible, new players use the
+                    // device either way.
+                    let failed: Vec<_> = self
+                        .streams
+                        .iter()
+                        .filter_map(|(id, stream)| {
+                            let player = &stream.read().unwrap().player;
+                            player.set_audio_device(device).err().map(|err| (*id, err))
+                        })
+                        .collect();
+                    self.audio_device = args.device;
+                    if !failed.is_empty() {
+                        return Err(AudioDeviceError(failed).into());
+                    }
+                }
+                Ok(Value::Null)
+            }
+            "getAudioStats" => {
+                let args: TextureIdArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let stats = stream.read().unwrap().player.audio_stats();
+                Ok(to_value(AudioStatsResult {
+                    underruns: stats.underruns as _,
+                    silent_samples: stats.silent_samples as _,
+                })?)
+            }
+            "getMetadata" => {
+                let args: TextureIdArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let metadata = stream.read().unwrap().player.metadata()?;
+                Ok(to_value(metadata_result(metadata))?)
+            }
+            "stepForward" => {
+                let args: TextureIdArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream.read().unwrap().player.step_forward()?;
+                Ok(Value::Null)
+            }
+            "stepBackward" => {
+                let args: TextureIdArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                stream.read().unwrap().player.step_backward()?;
+                Ok(Value::Null)
+            }
+            "captureFrame" => {
+                let args: CaptureFrameArgs = from_value(&call.args)?;
+                let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
+                let format = args.format.unwrap_or_default();
+                let max_size = args.max_size.map(|size| size.max(1) as u32);
+                // Encoded without holding the stream, which event forwarding
+                // and disposing wait for.
+                let frame = stream.read().unwrap().player.current_frame()?;
+                Ok(Value::U8List(frame.encode(format, max_size)?))
+            }
+            // Reads the file independently of any player.
+            "generateThumbnails" => {
+                let args: GenerateThumbnailsArgs = from_value(&call.args)?;
+                let path = match (&args.asset, &args.uri) {
+                    (Some(asset), _) => engine.assets().join(asset),
+                    (None, Some(uri)) if uri.starts_with("file://") => {
+                        PathBuf::from(&uri["file://".len()..])
+                    }
+                    _ => return Err(PlayerError::NoSource.into()),
+                };
+                let times: Vec<Duration> = args
+                    .timestamps
+                    .iter()
+                    .map(|&ms| Duration::from_millis(ms.max(0) as u64))
+                    .collect();
+                let format = args.format.unwrap_or_default();
+                let max_size = args.max_size.map(|size| size.max(1) as u32);
+                let images = snapshot::thumbnails(&path, &times, format, max_size)?;
+                Ok(Value::List(images.into_iter().map(Value::U8List).collect()))
+            }
             "seekTo" => {
                 let args: SeekToArgs = from_value(&call.args)?;
                 let stream = self.streams.get(&args.texture_id).ok_or(InvalidTextureId)?;
-                stream.read().unwrap().player.seek_to(args.location);
+                stream.read().unwrap().player.seek_to(args.location)?;
                 Ok(Value::Null)
             }
             "dispose" => {
                 let args: TextureIdArgs = from_value(&call.args)?;
-                self.streams
-                    .remove(&args.texture_id)
-                    .ok_or(InvalidTextureId)?;
+                self.dispose(args.texture_id, &engine)?;
+                self.update_ducking();
                 Ok(Value::Null)
             }
             _ => Err(MethodCallError::NotImplemented),
@@ -129,12 +504,32 @@ impl MethodCallHandler for Handler {
 
 struct StreamHandler {
     channel: String,
+    texture_id: i64,
     player: Player,
+    focus: Weak<Mutex<Focus>>,
+    /// Thread forwarding player events once listened to, dropping the sender
+    /// stops it.
+    forwarding: Option<(Sender<()>, JoinHandle<()>)>,
 }
 
 impl StreamHandler {
-    fn new(channel: String, player: Player) -> Self {
-        Self { channel, player }
+    fn new(channel: String, texture_id: i64, player: Player, focus: &Arc<Mutex<Focus>>) -> Self {
+        Self {
+            channel,
+            texture_id,
+            player,
+            focus: Arc::downgrade(focus),
+            forwarding: None,
+        }
+    }
+}
+
+impl Drop for StreamHandler {
+    fn drop(&mut self) {
+        if let Some((stop, thread)) = self.forwarding.take() {
+            drop(stop);
+            thread.join().ok();
+        }
     }
 }
 
@@ -144,17 +539,29 @@ impl EventHandler for StreamHandler {
         _value: Value,
         engine: FlutterEngine,
     ) -> Result<Value, MethodCallError> {
-        let channel_name = self.channel.clone();
-        let width = self.player.width();
-        let height = self.player.height();
-        engine.run_on_platform_thread(move |engine| {
-            engine.with_channel(&channel_name, move |channel| {
-                if let Some(channel) = channel.try_as_method_channel() {
-                    let value = to_value(VideoEvent::initialized(width, height, 1)).unwrap();
-                    channel.send_success_event(&value);
+        if self.forwarding.is_none() {
+            let channel_name = self.channel.clone();
+            let texture_id = self.texture_id;
+            let focus = self.focus.clone();
+            let events = self.player.events();
+            let (stop, stopped) = channel::bounded::<()>(0);
+            let thread = thread::spawn(move || loop {
+                let event = select! {
+                    recv(events) -> event => match event {
+                        Ok(event) => event,
+                        Err(_) => break,
+                    },
+                    recv(stopped) -> _ => break,
+                };
+                if let PlayerEvent::Completed | PlayerEvent::Error(_) = &event {
+                    if let Some(focus) = focus.upgrade() {
+                        focus.lock().unwrap().on_stop(texture_id);
+                    }
                 }
+                send_event(&engine, channel_name.clone(), video_event(event));
             });
-        });
+            self.forwarding = Some((stop, thread));
+        }
         Ok(Value::Null)
     }
 
@@ -162,3 +569,165 @@ impl EventHandler for StreamHandler {
         Ok(Value::Null)
     }
 }
+
+fn metadata_result(metadata: Metadata) -> MetadataResult {
+    let millis = |time: Duration| time.as_millis() as i64;
+    let tracks = metadata
+        .tracks
+        .into_iter()
+        .map(|track| TrackMetadataResult {
+            number: track.number as _,
+            kind: match track.kind {
+                TrackKind::Video => "video",
+                TrackKind::Audio => "audio",
+                TrackKind::Subtitle => "subtitle",
+                TrackKind::Other => "other",
+            }
+            .to_string(),
+            name: track.name,
+            language: track.language,
+            codec_id: track.codec_id,
+            codec_name: track.codec_name,
+            width: track.width.map(|width| width as _),
+            height: track.height.map(|height| height as _),
+            sample_rate: track.sample_rate,
+            channels: track.channels.map(|channels| channels as _),
+            tags: track.tags,
+        })
+        .collect();
+    let attachments = metadata
+        .attachments
+        .into_iter()
+        .map(|attachment| AttachmentResult {
+            file_name: attachment.file_name,
+            mime_type: attachment.mime_type,
+            description: attachment.description,
+            data: Bytes(attachment.data),
+        })
+        .collect();
+    MetadataResult {
+        title: metadata.title,
+        duration: metadata.duration.map(millis),
+        bitrate: metadata.bitrate.map(|bitrate| bitrate as _),
+        date: metadata
+            .date
+            .map(|date| match date.duration_since(UNIX_EPOCH) {
+                Ok(since) => millis(since),
+                Err(err) => -millis(err.duration()),
+            }),
+        muxing_app: metadata.muxing_app,
+        writing_app: metadata.writing_app,
+        tags: metadata.tags,
+        tracks,
+        attachments,
+    }
+}
+
+fn video_event(event: PlayerEvent) -> VideoEvent {
+    let millis = |duration: Duration| duration.as_millis() as i64;
+    let index = |index: Option<usize>| index.map(|index| index as i64);
+    match event {
+        PlayerEvent::Initialized {
+            width,
+            height,
+            duration,
+        } => VideoEvent::initialized(width, height, duration.map(millis).unwrap_or_default()),
+        PlayerEvent::StateChanged(state) => VideoEvent::state_changed(state),
+        PlayerEvent::Position(position) => VideoEvent::position(millis(position)),
+        PlayerEvent::Buffering(true) => VideoEvent::buffering_start(),
+        PlayerEvent::Buffering(false) => VideoEvent::buffering_end(),
+        PlayerEvent::Error(message) => VideoEvent::error(message),
+        PlayerEvent::Completed => VideoEvent::completed(),
+        PlayerEvent::TracksChanged { video, audio } => {
+            VideoEvent::tracks_changed(index(video), index(audio))
+        }
+        PlayerEvent::SizeChanged { width, height } => VideoEvent::size_changed(width, height),
+        PlayerEvent::AudioDeviceChanged(device) => VideoEvent::audio_device_changed(device),
+        PlayerEvent::AudioLevels(levels) => VideoEvent::audio_levels(levels),
+    }
+}
+
+fn send_event(engine: &FlutterEngine, channel_name: String, event: VideoEvent) {
+    let value = to_value(event).unwrap();
+    engine.run_on_platform_thread(move |engine| {
+        engine.with_channel(&channel_name, move |channel| {
+            if let Some(channel) = channel.try_as_method_channel() {
+                channel.send_success_event(&value);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::PlayerState;
+
+    #[test]
+    fn maps_player_events() {
+        let levels = meter::AudioLevels {
+            rms: vec![-20.0, -21.0],
+            peak: vec![-3.0, -4.0],
+            spectrum: vec![-40.0; 4],
+        };
+        let cases = vec![
+            (
+                PlayerEvent::Initialized {
+                    width: 640,
+                    height: 360,
+                    duration: Some(Duration::from_millis(12_345)),
+                },
+                VideoEvent::initialized(640, 360, 12_345),
+            ),
+            (
+                PlayerEvent::Initialized {
+                    width: 640,
+                    height: 360,
+                    duration: None,
+                },
+                VideoEvent::initialized(640, 360, 0),
+            ),
+            (
+                PlayerEvent::StateChanged(PlayerState::Paused),
+                VideoEvent::state_changed(PlayerState::Paused),
+            ),
+            (
+                PlayerEvent::Position(Duration::from_micros(1_500_900)),
+                VideoEvent::position(1_500),
+            ),
+            (PlayerEvent::Buffering(true), VideoEvent::buffering_start()),
+            (PlayerEvent::Buffering(false), VideoEvent::buffering_end()),
+            (
+                PlayerEvent::Error("broken".into()),
+                VideoEvent::error("broken".into()),
+            ),
+            (PlayerEvent::Completed, VideoEvent::completed()),
+            (
+                PlayerEvent::TracksChanged {
+                    video: Some(0),
+                    audio: None,
+                },
+                VideoEvent::tracks_changed(Some(0), None),
+            ),
+            (
+                PlayerEvent::SizeChanged {
+                    width: 1280,
+                    height: 720,
+                },
+                VideoEvent::size_changed(1280, 720),
+            ),
+            (
+                PlayerEvent::AudioDeviceChanged("speakers".into()),
+                VideoEvent::audio_device_changed("speakers".into()),
+            ),
+            (
+                PlayerEvent::AudioLevels(levels.clone()),
+                VideoEvent::audio_levels(levels),
+            ),
+        ];
+        for (event, expected) in cases {
+            let description = format!("{:?}", event);
+            assert_eq!(video_event(event), expected, "{}", description);
+        }
+    }
+}
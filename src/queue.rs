@@ -0,0 +1,212 @@
+//! Bounded queues of demuxed packets waiting to be decoded.
+use av_data::frame::ArcFrame;
+use av_data::packet::Packet;
+use av_data::rational::Rational64;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// How much demuxed data is buffered ahead of the decoders.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReadAhead {
+    /// Demuxing pauses once every stream has this much queued.
+    pub duration: Duration,
+    /// Demuxing pauses once the queues of all streams hold this many bytes.
+    pub bytes: usize,
+}
+
+impl Default for ReadAhead {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(2),
+            bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Where playback continues after a seek.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeekTarget {
+    /// The first frame at or after the time.
+    At(Duration),
+    /// The last video frame before the time, for stepping backward.
+    Before(Duration),
+}
+
+impl SeekTarget {
+    pub fn time(self) -> Duration {
+        match self {
+            Self::At(time) | Self::Before(time) => time,
+        }
+    }
+}
+
+/// A seek requested by a `Player`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Seek {
+    pub target: SeekTarget,
+    /// Increases with every seek of a player, so consumers can tell whether
+    /// they caught up with the latest one.
+    pub generation: u64,
+}
+
+/// Sent by a stream decoder to the thread presenting the stream.
+pub enum Decoded {
+    Frame(ArcFrame),
+    /// Frames sent earlier precede the seek.
+    Flush(Seek),
+    /// Every frame up to the end of the file was sent, more only follow
+    /// after a `Flush`.
+    End,
+}
+
+/// Popped from a `SharedQueue`.
+pub enum Queued {
+    Packet(Packet),
+    /// Packets popped earlier precede the seek.
+    Flush(Seek),
+}
+
+/// Presentation time of a packet or frame with timestamp `pts` in
+/// `timebase` units.
+pub fn timestamp(pts: Option<i64>, timebase: Option<Rational64>) -> Option<Duration> {
+    let nanos = Rational64::from_integer(pts? * 1_000_000_000) * timebase?;
+    Some(Duration::from_nanos(nanos.to_integer().max(0) as u64))
+}
+
+/// Packets of one stream in demuxing order.
+#[derive(Default)]
+pub struct PacketQueue {
+    packets: VecDeque<Packet>,
+    bytes: usize,
+}
+
+impl PacketQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        self.bytes += packet.data.len();
+        self.packets.push_back(packet);
+    }
+
+    pub fn pop(&mut self) -> Option<Packet> {
+        let packet = self.packets.pop_front()?;
+        self.bytes -= packet.data.len();
+        Some(packet)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Size of the queued packets.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Time between the first and the last queued packet, zero if their
+    /// timestamps are unknown.
+    pub fn duration(&self) -> Duration {
+        let time = |packet: &Packet| timestamp(packet.t.pts, packet.t.timebase);
+        let first = self.packets.front().and_then(time);
+        let last = self.packets.back().and_then(time);
+        match (first, last) {
+            (Some(first), Some(last)) if last > first => last - first,
+            _ => Duration::from_secs(0),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SharedState {
+    packets: PacketQueue,
+    /// Popped before the packets.
+    flush: Option<Seek>,
+    /// No more packets will be pushed before the next flush.
+    finished: bool,
+    /// The decoder stopped, pushed packets are discarded.
+    closed: bool,
+}
+
+/// A `PacketQueue` shared by the demuxer and the decoder of a stream.
+#[derive(Clone, Default)]
+pub struct SharedQueue {
+    inner: Arc<(Mutex<SharedState>, Condvar)>,
+}
+
+impl SharedQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, packet: Packet) {
+        let (state, available) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        if !state.closed {
+            state.packets.push(packet);
+            available.notify_one();
+        }
+    }
+
+    /// Waits up to `timeout` for a packet or flush.
+    pub fn pop(&self, timeout: Duration) -> Option<Queued> {
+        let (state, available) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        if state.packets.is_empty() && state.flush.is_none() && !state.finished {
+            state = available.wait_timeout(state, timeout).unwrap().0;
+        }
+        match state.flush.take() {
+            Some(seek) => Some(Queued::Flush(seek)),
+            None => state.packets.pop().map(Queued::Packet),
+        }
+    }
+
+    /// Discards the queued packets, the decoder pops a flush for `seek`
+    /// before the packets pushed afterwards. Replaces a flush not popped yet.
+    pub fn flush(&self, seek: Seek) {
+        let (state, available) = &*self.inner;
+        let mut state = state.lock().unwrap();
+        if !state.closed {
+            state.packets = PacketQueue::new();
+            state.flush = Some(seek);
+            state.finished = false;
+            available.notify_one();
+        }
+    }
+
+    /// Marks the end of the stream.
+    pub fn finish(&self) {
+        let (state, available) = &*self.inner;
+        state.lock().unwrap().finished = true;
+        available.notify_one();
+    }
+
+    /// Whether the end of the stream was reached and every packet popped.
+    pub fn is_finished(&self) -> bool {
+        let state = self.inner.0.lock().unwrap();
+        state.finished && state.flush.is_none() && state.packets.is_empty()
+    }
+
+    /// Discards the queued packets and the packets pushed later.
+    pub fn close(&self) {
+        let mut state = self.inner.0.lock().unwrap();
+        state.closed = true;
+        state.packets = PacketQueue::new();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.0.lock().unwrap().closed
+    }
+
+    /// Size of the queued packets.
+    pub fn bytes(&self) -> usize {
+        self.inner.0.lock().unwrap().packets.bytes()
+    }
+
+    /// Time between the first and the last queued packet.
+    pub fn duration(&self) -> Duration {
+        self.inner.0.lock().unwrap().packets.duration()
+    }
+}
@@ -0,0 +1,174 @@
+//! Lock-free single producer, single consumer ring buffer.
+//!
+//! Used to hand samples from the decoder side to real-time audio callbacks,
+//! which must never block or allocate.
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Inner<T> {
+    buf: Box<[UnsafeCell<T>]>,
+    /// Total number of elements read.
+    head: AtomicUsize,
+    /// Total number of elements written.
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Inner<T> {
+    fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        let head = self.head.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+}
+
+/// Creates a ring buffer holding up to `capacity` elements.
+pub fn ring_buffer<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let buf = (0..capacity.max(1))
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect::<Vec<_>>()
+        .into_boxed_slice();
+    let inner = Arc::new(Inner {
+        buf,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            inner: inner.clone(),
+        },
+        Consumer { inner },
+    )
+}
+
+pub struct Producer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Copy> Producer<T> {
+    /// Number of elements pushed but not popped yet.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Number of elements that can be pushed without overwriting unread data.
+    pub fn free_len(&self) -> usize {
+        self.inner.capacity() - self.inner.len()
+    }
+
+    /// Pushes as many elements of `data` as fit and returns how many were
+    /// written.
+    pub fn push_slice(&mut self, data: &[T]) -> usize {
+        let capacity = self.inner.capacity();
+        let tail = self.inner.tail.load(Ordering::Relaxed);
+        let len = data.len().min(self.free_len());
+        for (i, value) in data[..len].iter().enumerate() {
+            let cell = &self.inner.buf[tail.wrapping_add(i) % capacity];
+            // The consumer never reads slots between `tail` and `head + capacity`.
+            unsafe { *cell.get() = *value };
+        }
+        self.inner
+            .tail
+            .store(tail.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+pub struct Consumer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Number of elements available to pop.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Pops up to `out.len()` elements into `out` and returns how many were
+    /// read.
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+        let capacity = self.inner.capacity();
+        let head = self.inner.head.load(Ordering::Relaxed);
+        let len = out.len().min(self.inner.len());
+        for (i, value) in out[..len].iter_mut().enumerate() {
+            let cell = &self.inner.buf[head.wrapping_add(i) % capacity];
+            // The producer never writes slots between `head` and `tail`.
+            *value = unsafe { *cell.get() };
+        }
+        self.inner
+            .head
+            .store(head.wrapping_add(len), Ordering::Release);
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_and_full() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(4);
+        let mut out = [0; 4];
+        assert_eq!(consumer.pop_slice(&mut out), 0);
+        assert_eq!(producer.free_len(), 4);
+
+        assert_eq!(producer.push_slice(&[1, 2, 3, 4]), 4);
+        assert_eq!(producer.free_len(), 0);
+        assert_eq!(producer.push_slice(&[5]), 0);
+        assert_eq!(consumer.len(), 4);
+
+        assert_eq!(consumer.pop_slice(&mut out), 4);
+        assert_eq!(out, [1, 2, 3, 4]);
+        assert_eq!(consumer.len(), 0);
+        assert_eq!(producer.free_len(), 4);
+    }
+
+    #[test]
+    fn partial_counts() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(4);
+        assert_eq!(producer.push_slice(&[1, 2, 3, 4, 5, 6]), 4);
+        let mut out = [0; 3];
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(out, [1, 2, 3]);
+        assert_eq!(consumer.pop_slice(&mut out), 1);
+        assert_eq!(out[0], 4);
+    }
+
+    #[test]
+    fn wraparound() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(3);
+        let mut next = 0;
+        let mut expected = 0;
+        let mut out = [0; 2];
+        for _ in 0..10 {
+            let data = [next, next + 1];
+            next += producer.push_slice(&data) as i32;
+            let read = consumer.pop_slice(&mut out);
+            for value in &out[..read] {
+                assert_eq!(*value, expected);
+                expected += 1;
+            }
+        }
+        assert_eq!(expected, next);
+        assert_eq!(consumer.len(), 0);
+    }
+
+    #[test]
+    fn wrapping_counters() {
+        let (mut producer, mut consumer) = ring_buffer::<i32>(2);
+        producer.inner.head.store(usize::MAX, Ordering::Relaxed);
+        producer.inner.tail.store(usize::MAX, Ordering::Relaxed);
+        assert_eq!(producer.push_slice(&[1, 2]), 2);
+        assert_eq!(consumer.len(), 2);
+        let mut out = [0; 2];
+        assert_eq!(consumer.pop_slice(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+    }
+}
@@ -0,0 +1,344 @@
+//! Audio sinks that don't need an output device, for headless tests.
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Length of the buffers rendered by a `ThreadSink`.
+const PERIOD_MS: usize = 10;
+
+/// Where players send their audio.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioSink {
+    /// The selected or default output device.
+    Device,
+    /// Discards the samples.
+    Null {
+        /// Consume samples at the playback rate, otherwise as fast as they
+        /// are decoded.
+        realtime: bool,
+    },
+    /// Writes the samples to a 16 bit WAV file, players created later
+    /// overwrite it.
+    Wav { path: PathBuf, realtime: bool },
+}
+
+impl AudioSink {
+    /// Whether samples go to an output device.
+    pub fn is_device(&self) -> bool {
+        *self == Self::Device
+    }
+}
+
+impl Default for AudioSink {
+    fn default() -> Self {
+        Self::Device
+    }
+}
+
+/// Error parsing an `AudioSink`.
+#[derive(Debug)]
+pub struct InvalidAudioSink(String);
+
+impl std::fmt::Display for InvalidAudioSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid audio sink {:?}, expected device, null, null-fast, wav:PATH or wav-fast:PATH",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidAudioSink {}
+
+impl FromStr for AudioSink {
+    type Err = InvalidAudioSink;
+
+    /// Parses `device`, `null`, `null-fast`, `wav:PATH` or `wav-fast:PATH`,
+    /// the `-fast` variants don't wait for the playback clock.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = match s.find(':') {
+            Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+            None => (s, None),
+        };
+        match (kind, path) {
+            ("device", None) => Ok(Self::Device),
+            ("null", None) => Ok(Self::Null { realtime: true }),
+            ("null-fast", None) => Ok(Self::Null { realtime: false }),
+            ("wav", Some(path)) if !path.is_empty() => Ok(Self::Wav {
+                path: path.into(),
+                realtime: true,
+            }),
+            ("wav-fast", Some(path)) if !path.is_empty() => Ok(Self::Wav {
+                path: path.into(),
+                realtime: false,
+            }),
+            _ => Err(InvalidAudioSink(s.to_string())),
+        }
+    }
+}
+
+/// Pulls samples on its own thread instead of a device callback.
+///
+/// The render function fills the start of the buffer and returns how many
+/// samples it wrote.
+pub struct ThreadSink {
+    playing: Arc<AtomicBool>,
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadSink {
+    pub fn new<F>(sink: &AudioSink, channels: usize, rate: usize, mut render: F) -> io::Result<Self>
+    where
+        F: FnMut(&mut [f32]) -> usize + Send + 'static,
+    {
+        let (mut wav, realtime) = match sink {
+            AudioSink::Device => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "device sinks are played by cpal",
+                ))
+            }
+            AudioSink::Null { realtime } => (None, *realtime),
+            AudioSink::Wav { path, realtime } => {
+                (Some(WavWriter::create(path, channels, rate)?), *realtime)
+            }
+        };
+        let playing = Arc::new(AtomicBool::new(false));
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let playing = playing.clone();
+            let closed = closed.clone();
+            thread::spawn(move || {
+                let channels = channels.max(1);
+                let mut buffer = vec![0.0; rate * PERIOD_MS / 1000 * channels];
+                let mut clock: Option<(Instant, usize)> = None;
+                while !closed.load(Ordering::Relaxed) {
+                    if !playing.load(Ordering::Relaxed) {
+                        clock = None;
+                        thread::sleep(Duration::from_millis(PERIOD_MS as u64));
+                        continue;
+                    }
+                    let written = render(&mut buffer);
+                    if let Some(writer) = &mut wav {
+                        if let Err(err) = writer.write(&buffer[..written]) {
+                            log::error!("failed to write wav file: {}", err);
+                            wav = None;
+                        }
+                    }
+                    if !realtime {
+                        if written == 0 {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                        continue;
+                    }
+                    // Keep pace with the playback rate from when playing
+                    // started.
+                    let (start, frames) = clock.get_or_insert_with(|| (Instant::now(), 0));
+                    *frames += written / channels;
+                    let elapsed = *frames as u64 * 1_000_000_000 / rate.max(1) as u64;
+                    let due = *start + Duration::from_nanos(elapsed);
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    }
+                }
+                if let Some(wav) = wav {
+                    if let Err(err) = wav.finish() {
+                        log::error!("failed to write wav file: {}", err);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            playing,
+            closed,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ThreadSink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Largest data chunk whose RIFF size still fits the 32 bit header field,
+/// rounded down to whole samples.
+const MAX_DATA_LEN: u32 = (u32::MAX - 36) & !1;
+
+/// Writes 16 bit PCM WAV files, the sizes in the header are filled in by
+/// `finish`.
+///
+/// Samples beyond the 4 GiB size limit of the format are dropped.
+struct WavWriter {
+    writer: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path, channels: usize, rate: usize) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let channels = channels as u16;
+        let rate = rate as u32;
+        let block_align = channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&rate.to_le_bytes())?;
+        writer.write_all(&(rate * u32::from(block_align)).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let room = ((MAX_DATA_LEN - self.data_len) / 2) as usize;
+        if samples.len() > room && room > 0 {
+            log::warn!("wav file reached the 4 GiB limit, dropping further samples");
+        }
+        let samples = &samples[..samples.len().min(room)];
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * 32_767.0) as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(self.data_len + 36).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", std::process::id(), name))
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        let mut field = [0; 4];
+        field.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(field)
+    }
+
+    #[test]
+    fn parses_sinks() {
+        assert_eq!("device".parse::<AudioSink>().unwrap(), AudioSink::Device);
+        assert_eq!(
+            "null-fast".parse::<AudioSink>().unwrap(),
+            AudioSink::Null { realtime: false }
+        );
+        assert_eq!(
+            "wav:out.wav".parse::<AudioSink>().unwrap(),
+            AudioSink::Wav {
+                path: "out.wav".into(),
+                realtime: true
+            }
+        );
+        assert!("wav:".parse::<AudioSink>().is_err());
+        assert!("speaker".parse::<AudioSink>().is_err());
+    }
+
+    #[test]
+    fn rejects_device_sink() {
+        let sink = ThreadSink::new(&AudioSink::Device, 2, 48_000, |buffer| buffer.len());
+        assert_eq!(sink.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn plays_to_wav() {
+        let path = temp_path("plays_to_wav.wav");
+        let sink = AudioSink::Wav {
+            path: path.clone(),
+            realtime: false,
+        };
+        // Three and a half periods of a stereo ramp, then silence.
+        let total = 48_000 * PERIOD_MS / 1000 * 2 * 7 / 2;
+        let mut rendered = 0;
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        let thread_sink = ThreadSink::new(&sink, 2, 48_000, move |buffer| {
+            let len = buffer.len().min(total - rendered);
+            for (i, sample) in buffer[..len].iter_mut().enumerate() {
+                *sample = ((rendered + i) % 100) as f32 / 100.0;
+            }
+            rendered += len;
+            if len == 0 {
+                done_tx.send(()).ok();
+            }
+            len
+        })
+        .unwrap();
+        thread_sink.play();
+        done_rx.recv().unwrap();
+        drop(thread_sink);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 2);
+        assert_eq!(read_u32(&bytes, 24), 48_000);
+        assert_eq!(read_u32(&bytes, 28), 48_000 * 4);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(read_u32(&bytes, 40) as usize, total * 2);
+        assert_eq!(bytes.len(), 44 + total * 2);
+        let sample = |i: usize| i16::from_le_bytes([bytes[44 + i * 2], bytes[45 + i * 2]]);
+        assert_eq!(sample(0), 0);
+        assert_eq!(sample(150), (0.5 * 32_767.0) as i16);
+    }
+
+    #[test]
+    fn stops_at_size_limit() {
+        let path = temp_path("stops_at_size_limit.wav");
+        let mut writer = WavWriter::create(&path, 1, 48_000).unwrap();
+        writer.data_len = MAX_DATA_LEN - 4;
+        writer.write(&[0.0; 10]).unwrap();
+        assert_eq!(writer.data_len, MAX_DATA_LEN);
+        writer.write(&[0.0; 10]).unwrap();
+        assert_eq!(writer.data_len, MAX_DATA_LEN);
+        writer.finish().unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(bytes.len(), 44 + 4);
+        assert_eq!(read_u32(&bytes, 4), MAX_DATA_LEN + 36);
+        assert_eq!(read_u32(&bytes, 40), MAX_DATA_LEN);
+    }
+}
@@ -0,0 +1,167 @@
+//! Encoding of video frames as image files.
+use crate::player::{PlaybackContext, PlayerError};
+use crate::queue::timestamp;
+use crate::video;
+use av_data::frame::ArcFrame;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageError, ImageOutputFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Quality of JPEG images, from 1 to 100.
+const JPEG_QUALITY: u8 = 90;
+
+/// Format of an encoded frame.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        Self::Png
+    }
+}
+
+/// Scales `image` down to fit into `max_size` pixels in both dimensions,
+/// keeping its aspect ratio. Smaller images are returned as is.
+pub fn scale(image: RgbaImage, max_size: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let longest = width.max(height);
+    if longest <= max_size || max_size == 0 {
+        return image;
+    }
+    let scaled =
+        |side: u32| ((u64::from(side) * u64::from(max_size) / u64::from(longest)) as u32).max(1);
+    imageops::resize(&image, scaled(width), scaled(height), FilterType::Triangle)
+}
+
+/// Encodes `image` as `format`, scaled down to `max_size` if given.
+pub fn encode(
+    image: RgbaImage,
+    format: ImageFormat,
+    max_size: Option<u32>,
+) -> Result<Vec<u8>, ImageError> {
+    let image = match max_size {
+        Some(max_size) => scale(image, max_size),
+        None => image,
+    };
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Png => {
+            DynamicImage::ImageRgba8(image).write_to(&mut bytes, ImageOutputFormat::PNG)?
+        }
+        // JPEG has no alpha channel.
+        ImageFormat::Jpeg => {
+            let image = DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(image).to_rgb());
+            image.write_to(&mut bytes, ImageOutputFormat::JPEG(JPEG_QUALITY))?
+        }
+    }
+    Ok(bytes)
+}
+
+/// A presented video frame, see `Player::current_frame`.
+///
+/// Converting and encoding the frame is slow, it can be sent to another
+/// thread to do that.
+pub struct VideoFrame(ArcFrame);
+
+impl VideoFrame {
+    pub(crate) fn new(frame: ArcFrame) -> Self {
+        Self(frame)
+    }
+
+    /// Converts the frame to RGBA.
+    pub fn image(&self) -> Result<RgbaImage, PlayerError> {
+        video::frame_image(&self.0).ok_or(PlayerError::NoFrame)
+    }
+
+    /// Encodes the frame as `format`, scaled down to fit into `max_size`
+    /// pixels if given.
+    pub fn encode(
+        &self,
+        format: ImageFormat,
+        max_size: Option<u32>,
+    ) -> Result<Vec<u8>, PlayerError> {
+        Ok(encode(self.image()?, format, max_size)?)
+    }
+}
+
+/// Encodes the video frames nearest to `times` like `encode`, in the order
+/// of `times`.
+///
+/// The file is read independently of any player playing it. Each time is
+/// decoded from the keyframe before it, in the order of the times.
+pub fn thumbnails(
+    path: &Path,
+    times: &[Duration],
+    format: ImageFormat,
+    max_size: Option<u32>,
+) -> Result<Vec<Vec<u8>>, PlayerError> {
+    let mut context = PlaybackContext::from_path(path)?;
+    context.disable_audio();
+    if context.video.is_none() {
+        return Err(PlayerError::NoVideo);
+    }
+    let mut order: Vec<usize> = (0..times.len()).collect();
+    order.sort_by_key(|&index| times[index]);
+    let mut images = vec![Vec::new(); times.len()];
+    let mut encoded = Encoded::default();
+    for index in order {
+        let (frame, pts) = nearest_frame(&mut context, times[index])?;
+        images[index] = encoded.get((&frame, pts), format, max_size)?;
+    }
+    Ok(images)
+}
+
+/// Decodes the frame with the timestamp closest to `time`, the last frame
+/// for times past the end.
+fn nearest_frame(
+    context: &mut PlaybackContext,
+    time: Duration,
+) -> Result<(ArcFrame, Duration), PlayerError> {
+    context.seek(time)?;
+    let mut before: Option<(ArcFrame, Duration)> = None;
+    while !context.is_eof() {
+        let frame = match context.decode_one()? {
+            Some(frame) => frame,
+            None => continue,
+        };
+        let pts = timestamp(frame.t.pts, frame.t.timebase).unwrap_or_default();
+        if pts < time {
+            before = Some((frame, pts));
+            continue;
+        }
+        return Ok(match before {
+            Some((before, before_pts)) if time - before_pts < pts - time => (before, before_pts),
+            _ => (frame, pts),
+        });
+    }
+    before.ok_or(PlayerError::NoFrame)
+}
+
+/// The most recently encoded frame, reused when several times are nearest
+/// to the same frame.
+#[derive(Default)]
+struct Encoded(Option<(Duration, Vec<u8>)>);
+
+impl Encoded {
+    fn get(
+        &mut self,
+        (frame, pts): (&ArcFrame, Duration),
+        format: ImageFormat,
+        max_size: Option<u32>,
+    ) -> Result<Vec<u8>, PlayerError> {
+        match &self.0 {
+            Some((encoded_pts, bytes)) if *encoded_pts == pts => Ok(bytes.clone()),
+            _ => {
+                let bytes = VideoFrame::new(frame.clone()).encode(format, max_size)?;
+                self.0 = Some((pts, bytes.clone()));
+                Ok(bytes)
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Pitch preserving time stretching using WSOLA (waveform similarity based
+//! overlap-add).
+//!
+//! Output is assembled from overlapping, Hann windowed segments of the input.
+//! Segments are taken `speed` times further apart in the input than they are
+//! placed in the output, and each one is shifted by a few milliseconds to the
+//! position that best continues the waveform of the previous segment.
+
+/// Length of a segment.
+const WINDOW_MS: usize = 40;
+/// How far a segment may be moved to line up with the previous one.
+const SEARCH_MS: usize = 10;
+/// Only every n-th frame is compared when searching, which is plenty for the
+/// low frequencies that dominate the similarity measure.
+const SEARCH_STEP: usize = 2;
+
+pub struct TimeStretch {
+    channels: usize,
+    speed: f64,
+    /// Segment length in frames.
+    window: usize,
+    /// Distance between segments in the output, half a segment.
+    hop: usize,
+    /// Maximum segment shift in frames.
+    search: usize,
+    hann: Vec<f32>,
+    /// Interleaved input that hasn't been consumed yet.
+    input: Vec<f32>,
+    /// Ideal input position of the next segment in frames.
+    position: f64,
+    /// Windowed second half of the previous segment.
+    tail: Vec<f32>,
+    /// Input following the previous segment, which the next one should
+    /// resemble. Empty before the first segment.
+    natural: Vec<f32>,
+}
+
+impl TimeStretch {
+    pub fn new(channels: usize, rate: usize) -> Self {
+        let channels = channels.max(1);
+        let hop = (rate * WINDOW_MS / 1000 / 2).max(1);
+        let window = hop * 2;
+        let hann = (0..window)
+            .map(|i| {
+                let phase = 2.0 * std::f64::consts::PI * i as f64 / window as f64;
+                (0.5 - 0.5 * phase.cos()) as f32
+            })
+            .collect();
+        Self {
+            channels,
+            speed: 1.0,
+            window,
+            hop,
+            search: rate * SEARCH_MS / 1000,
+            hann,
+            input: Vec::new(),
+            position: 0.0,
+            tail: vec![0.0; hop * channels],
+            natural: Vec::new(),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /// Discards buffered input, for example after a seek.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.position = 0.0;
+        for sample in &mut self.tail {
+            *sample = 0.0;
+        }
+        self.natural.clear();
+    }
+
+    /// Stretches interleaved `input` and appends the result to `out`.
+    ///
+    /// Some input is held back until enough follows to place the next
+    /// segment, so output lags input by up to a segment and a search range.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if self.is_idle() && (self.speed - 1.0).abs() < f64::EPSILON {
+            out.extend_from_slice(input);
+            return;
+        }
+        self.input.extend_from_slice(input);
+        if (self.speed - 1.0).abs() < f64::EPSILON {
+            self.finish(out);
+        } else {
+            self.stretch(out);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.input.is_empty() && self.natural.is_empty()
+    }
+
+    fn frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    fn stretch(&mut self, out: &mut Vec<f32>) {
+        let channels = self.channels;
+        while (self.position as usize) + self.search * 2 + self.window <= self.frames() {
+            let ideal = self.position as usize;
+            let start = if self.natural.is_empty() {
+                ideal
+            } else {
+                self.best_start(ideal)
+            };
+
+            // The first segment continues unmodified input, so it isn't faded in.
+            let fade_in = !self.natural.is_empty();
+            let segment = &self.input[start * channels..(start + self.window) * channels];
+            for i in 0..self.hop {
+                let rise = if fade_in { self.hann[i] } else { 1.0 };
+                let fall = self.hann[self.hop + i];
+                for ch in 0..channels {
+                    let idx = i * channels + ch;
+                    out.push(self.tail[idx] + segment[idx] * rise);
+                    self.tail[idx] = segment[self.hop * channels + idx] * fall;
+                }
+            }
+            let natural = (start + self.hop) * channels..(start + self.window) * channels;
+            self.natural.clear();
+            self.natural.extend_from_slice(&self.input[natural]);
+
+            self.position += self.hop as f64 * self.speed;
+            self.consume();
+        }
+    }
+
+    /// Returns the start of the segment near `ideal` that best continues the
+    /// previous segment.
+    ///
+    /// `ideal` is at least `search` frames into the input, see `consume`.
+    fn best_start(&self, ideal: usize) -> usize {
+        let channels = self.channels;
+        let first = ideal.saturating_sub(self.search);
+        let last = ideal + self.search;
+        let mut best = ideal;
+        let mut best_score = f32::MIN;
+        for start in (first..=last).step_by(SEARCH_STEP) {
+            let candidate = &self.input[start * channels..(start + self.hop) * channels];
+            let mut score = 0.0;
+            let mut energy = 0.0;
+            for i in (0..self.hop).step_by(SEARCH_STEP) {
+                for ch in 0..channels {
+                    let idx = i * channels + ch;
+                    score += candidate[idx] * self.natural[idx];
+                    energy += candidate[idx] * candidate[idx];
+                }
+            }
+            let score = score / energy.sqrt().max(f32::EPSILON);
+            if score > best_score {
+                best_score = score;
+                best = start;
+            }
+        }
+        best
+    }
+
+    /// Drops input that no future segment can start in.
+    ///
+    /// At high speeds the next segment may start beyond the buffered input.
+    fn consume(&mut self) {
+        let position = self.position as usize;
+        if position > self.search {
+            let drop = (position - self.search).min(self.frames());
+            self.input.drain(..drop * self.channels);
+            self.position -= drop as f64;
+        }
+    }
+
+    /// Crossfades from the last segment back to the unmodified input once the
+    /// speed returns to normal.
+    fn finish(&mut self, out: &mut Vec<f32>) {
+        let channels = self.channels;
+        let start = self.position as usize;
+        if start + self.hop > self.frames() {
+            return;
+        }
+        for i in 0..self.hop {
+            let rise = self.hann[i];
+            for ch in 0..channels {
+                let idx = i * channels + ch;
+                out.push(self.tail[idx] + self.input[start * channels + idx] * rise);
+            }
+        }
+        out.extend_from_slice(&self.input[(start + self.hop) * channels..]);
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 48_000;
+
+    /// Stretches a second of a sine in 20 ms chunks, returns the number of
+    /// output frames.
+    fn stretched_frames(channels: usize, speed: f64) -> usize {
+        let mut stretch = TimeStretch::new(channels, RATE);
+        stretch.set_speed(speed);
+        let mut out = Vec::new();
+        let chunk = RATE / 50;
+        for n in 0..50 {
+            let input: Vec<f32> = (0..chunk * channels)
+                .map(|i| {
+                    let t = (n * chunk + i / channels) as f32 / RATE as f32;
+                    (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+                })
+                .collect();
+            stretch.process(&input, &mut out);
+        }
+        assert_eq!(out.len() % channels, 0);
+        out.len() / channels
+    }
+
+    #[test]
+    fn output_length_follows_speed() {
+        let stretch = TimeStretch::new(1, RATE);
+        // Input held back waiting for the next segment.
+        let latency = (stretch.window + stretch.search * 2 + stretch.hop) as f64;
+        for &channels in &[1, 2] {
+            for &speed in &[0.5, 0.75, 1.25, 1.5, 2.0, 4.0] {
+                let expected = RATE as f64 / speed;
+                let frames = stretched_frames(channels, speed) as f64;
+                assert!(
+                    frames <= expected && frames >= expected - latency / speed,
+                    "{} channels at {}x: {} frames, expected {}",
+                    channels,
+                    speed,
+                    frames,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn normal_speed_passes_input_through() {
+        assert_eq!(stretched_frames(2, 1.0), RATE);
+    }
+
+    #[test]
+    fn returning_to_normal_speed_flushes_held_input() {
+        let mut stretch = TimeStretch::new(1, RATE);
+        let mut out = Vec::new();
+        stretch.set_speed(2.0);
+        stretch.process(&vec![0.5; RATE], &mut out);
+        let stretched = out.len();
+        stretch.set_speed(1.0);
+        stretch.process(&vec![0.5; RATE], &mut out);
+        assert!(stretch.is_idle());
+        assert!(out.len() - stretched >= RATE);
+    }
+}
@@ -1,5 +1,11 @@
 #![allow(dead_code)]
-use serde::{Deserialize, Serialize};
+use crate::dsp::BandKind;
+use crate::focus::FocusPolicy;
+use crate::meter::AudioLevels;
+use crate::player::PlayerState;
+use crate::snapshot::ImageFormat;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -39,6 +45,83 @@ pub struct SetVolumeArgs {
     pub volume: f64,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetBalanceArgs {
+    pub texture_id: i64,
+    /// From `-1.0` (left) to `1.0` (right).
+    pub balance: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetLoudnessNormalizationArgs {
+    pub texture_id: i64,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EqualizerBandArgs {
+    pub kind: BandKind,
+    /// Center or corner frequency in Hz.
+    pub frequency: f64,
+    /// Gain in dB.
+    pub gain: f64,
+    pub q: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetEqualizerBandsArgs {
+    pub texture_id: i64,
+    pub bands: Vec<EqualizerBandArgs>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMutedArgs {
+    pub texture_id: i64,
+    pub muted: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetVolumeRampArgs {
+    pub texture_id: i64,
+    /// Ramp duration in milliseconds.
+    pub duration: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMeteringEnabledArgs {
+    pub texture_id: i64,
+    pub enabled: bool,
+    /// Interval between `audioLevels` events in milliseconds.
+    pub interval: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetPlaybackSpeedArgs {
+    pub texture_id: i64,
+    pub speed: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MixWithOthersArgs {
+    pub mix_with_others: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetAudioFocusPolicyArgs {
+    pub texture_id: Option<i64>,
+    pub policy: FocusPolicy,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SeekToArgs {
@@ -46,7 +129,113 @@ pub struct SeekToArgs {
     pub location: i64,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetAudioDeviceArgs {
+    pub texture_id: Option<i64>,
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureFrameArgs {
+    pub texture_id: i64,
+    /// PNG if not given.
+    pub format: Option<ImageFormat>,
+    /// Longest side of the image in pixels, the frame size if not given.
+    pub max_size: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateThumbnailsArgs {
+    /// A `file://` URI, used if no asset is given.
+    pub uri: Option<String>,
+    pub asset: Option<String>,
+    /// Times of the frames in milliseconds.
+    pub timestamps: Vec<i64>,
+    /// PNG if not given.
+    pub format: Option<ImageFormat>,
+    /// Longest side of the images in pixels, the frame size if not given.
+    pub max_size: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioDeviceResult {
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioStatsResult {
+    pub underruns: i64,
+    pub silent_samples: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataResult {
+    pub title: Option<String>,
+    /// Duration in milliseconds.
+    pub duration: Option<i64>,
+    /// Average bits per second.
+    pub bitrate: Option<i64>,
+    /// Creation date in milliseconds since the Unix epoch.
+    pub date: Option<i64>,
+    pub muxing_app: Option<String>,
+    pub writing_app: Option<String>,
+    /// Values of tags by name, in file order.
+    pub tags: HashMap<String, Vec<String>>,
+    pub tracks: Vec<TrackMetadataResult>,
+    pub attachments: Vec<AttachmentResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackMetadataResult {
+    pub number: i64,
+    /// `video`, `audio`, `subtitle` or `other`.
+    pub kind: String,
+    pub name: Option<String>,
+    pub language: String,
+    pub codec_id: String,
+    pub codec_name: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub sample_rate: Option<f64>,
+    pub channels: Option<i64>,
+    /// Values of tags by name, in file order.
+    pub tags: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentResult {
+    pub file_name: String,
+    pub mime_type: String,
+    pub description: Option<String>,
+    pub data: Bytes,
+}
+
+/// Serialized as a byte array instead of a list of integers.
+#[derive(Debug, PartialEq)]
+pub struct Bytes(pub Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Bytes)
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VideoEvent {
     event: VideoEventType,
@@ -54,6 +243,16 @@ pub struct VideoEvent {
     height: Option<i64>,
     duration: Option<i64>,
     values: Option<Vec<(i64, i64)>>,
+    device: Option<String>,
+    rms: Option<Vec<f64>>,
+    peak: Option<Vec<f64>>,
+    spectrum: Option<Vec<f64>>,
+    state: Option<PlayerState>,
+    /// Position in milliseconds.
+    position: Option<i64>,
+    message: Option<String>,
+    video_track: Option<i64>,
+    audio_track: Option<i64>,
 }
 
 impl VideoEvent {
@@ -95,9 +294,70 @@ impl VideoEvent {
             ..Default::default()
         }
     }
+
+    pub fn audio_device_changed(device: String) -> Self {
+        Self {
+            event: VideoEventType::AudioDeviceChanged,
+            device: Some(device),
+            ..Default::default()
+        }
+    }
+
+    pub fn state_changed(state: PlayerState) -> Self {
+        Self {
+            event: VideoEventType::StateChanged,
+            state: Some(state),
+            ..Default::default()
+        }
+    }
+
+    pub fn position(position: i64) -> Self {
+        Self {
+            event: VideoEventType::Position,
+            position: Some(position),
+            ..Default::default()
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Self {
+            event: VideoEventType::Error,
+            message: Some(message),
+            ..Default::default()
+        }
+    }
+
+    pub fn tracks_changed(video: Option<i64>, audio: Option<i64>) -> Self {
+        Self {
+            event: VideoEventType::TracksChanged,
+            video_track: video,
+            audio_track: audio,
+            ..Default::default()
+        }
+    }
+
+    pub fn size_changed(width: i64, height: i64) -> Self {
+        Self {
+            event: VideoEventType::SizeChanged,
+            width: Some(width),
+            height: Some(height),
+            ..Default::default()
+        }
+    }
+
+    pub fn audio_levels(levels: AudioLevels) -> Self {
+        let convert = |values: Vec<f32>| Some(values.into_iter().map(f64::from).collect());
+        Self {
+            event: VideoEventType::AudioLevels,
+            rms: convert(levels.rms),
+            peak: convert(levels.peak),
+            spectrum: convert(levels.spectrum),
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub enum VideoEventType {
     Initialized,
@@ -105,6 +365,13 @@ pub enum VideoEventType {
     BufferingUpdate,
     BufferingStart,
     BufferingEnd,
+    AudioDeviceChanged,
+    AudioLevels,
+    StateChanged,
+    Position,
+    Error,
+    TracksChanged,
+    SizeChanged,
     Unknown,
 }
 
@@ -1,124 +1,440 @@
 #![allow(clippy::many_single_char_names)]
+use crate::event::PlayerEvent;
+use crate::queue::{timestamp, Decoded, Seek, SeekTarget};
 use av_data::frame::{ArcFrame, FrameBufferConv, MediaKind};
 use av_data::params::VideoInfo;
-use av_data::rational::Rational64;
 use crossbeam::atomic::AtomicCell;
+use crossbeam::channel::Sender;
 use flutter_engine::texture_registry::Texture;
 use image::{Rgba, RgbaImage};
-use std::sync::mpsc::Receiver;
-use std::sync::Arc;
-use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
+/// How often a paused or waiting video thread checks its state.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether the video thread presents frames.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum PlayerState {
+enum StreamState {
     Playing,
     Paused,
     Stopped,
 }
 
+/// Time source the video thread paces frames with.
+trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Receives the frames presented by a `VideoStream`.
+pub trait FrameSink: Send {
+    /// Presents `frame`, `pts` is its presentation timestamp.
+    fn post_frame(&mut self, frame: RgbaImage, pts: Duration);
+}
+
+impl FrameSink for Texture {
+    fn post_frame(&mut self, frame: RgbaImage, _pts: Duration) {
+        self.post_frame_rgba(frame);
+    }
+}
+
+/// A frame recorded by a `MemoryFrameSink`.
+#[derive(Clone, Debug)]
+pub struct PostedFrame {
+    pub image: RgbaImage,
+    /// Presentation timestamp of the frame.
+    pub pts: Duration,
+    /// When the frame was posted.
+    pub posted_at: Instant,
+}
+
+/// Records posted frames in memory, for tests without a Flutter engine.
+///
+/// Clones share the recorded frames, so a clone can be kept to inspect the
+/// frames posted to the sink given to the player.
+#[derive(Clone, Default)]
+pub struct MemoryFrameSink {
+    frames: Arc<Mutex<Vec<PostedFrame>>>,
+}
+
+impl MemoryFrameSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the frames posted so far.
+    pub fn frames(&self) -> Vec<PostedFrame> {
+        self.frames.lock().unwrap().clone()
+    }
+
+    /// Number of frames posted so far.
+    pub fn len(&self) -> usize {
+        self.frames.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the frames posted so far.
+    pub fn clear(&self) {
+        self.frames.lock().unwrap().clear();
+    }
+}
+
+impl FrameSink for MemoryFrameSink {
+    fn post_frame(&mut self, frame: RgbaImage, pts: Duration) {
+        self.frames.lock().unwrap().push(PostedFrame {
+            image: frame,
+            pts,
+            posted_at: Instant::now(),
+        });
+    }
+}
+
 pub struct VideoStream {
-    state: Arc<AtomicCell<PlayerState>>,
+    state: Arc<AtomicCell<StreamState>>,
+    speed: Arc<AtomicCell<f64>>,
+    ended: Arc<AtomicBool>,
+    position: Arc<AtomicCell<Duration>>,
+    /// The frame presented last.
+    current: Arc<Mutex<Option<ArcFrame>>>,
+    /// Frames to present while paused.
+    steps: Arc<AtomicUsize>,
+    /// Generation of the latest seek.
+    seek: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
 }
 
 impl VideoStream {
+    /// Returns a flag set once the last frame was presented.
+    pub fn ended(&self) -> Arc<AtomicBool> {
+        self.ended.clone()
+    }
+
+    /// Presentation timestamp of the frame presented last.
+    pub fn position(&self) -> Duration {
+        self.position.load()
+    }
+
+    /// Returns the frame presented last, `None` before the first one.
+    pub fn current_frame(&self) -> Option<ArcFrame> {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Scales the time between frames by `1 / speed`.
+    pub fn set_speed(&self, speed: f64) {
+        self.speed.store(speed);
+    }
+
     pub fn play(&self) {
-        self.state.store(PlayerState::Playing);
+        self.state.store(StreamState::Playing);
     }
 
     pub fn pause(&self) {
-        self.state.store(PlayerState::Paused);
+        self.state.store(StreamState::Paused);
+    }
+
+    /// Presents the next frame while paused.
+    pub fn step(&self) {
+        self.steps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Discards frames until the flush of seek `generation` arrives and
+    /// presents the frame it lands on, even while paused.
+    pub fn seek(&self, generation: u64) {
+        self.steps.store(0, Ordering::Relaxed);
+        self.seek.store(generation, Ordering::Relaxed);
     }
 }
 
 impl Drop for VideoStream {
+    /// Stops the video thread and waits for it, which drops the frame sink.
     fn drop(&mut self) {
-        self.state.store(PlayerState::Stopped);
+        self.state.store(StreamState::Stopped);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
     }
 }
 
 pub struct VideoPlayer {
     width: usize,
     height: usize,
-    texture: Texture,
+    sink: Box<dyn FrameSink>,
+    clock: Arc<dyn Clock>,
 }
 
 impl VideoPlayer {
-    pub fn new(info: &VideoInfo, texture: Texture) -> Self {
+    pub fn new(info: &VideoInfo, sink: Box<dyn FrameSink>) -> Self {
         let width = info.width;
         let height = info.height;
         Self {
             width,
             height,
-            texture,
+            sink,
+            clock: Arc::new(SystemClock),
         }
     }
 
-    pub fn create_stream(self, rx: Receiver<ArcFrame>) -> VideoStream {
-        let width = self.width;
-        let height = self.height;
-        let texture = self.texture;
-        let state = Arc::new(AtomicCell::new(PlayerState::Paused));
+    /// Starts presenting the frames received from `rx`, reports size changes
+    /// and the position after seeks and steps to `events`.
+    ///
+    /// The first frame is presented even while paused, `preroll` receives
+    /// once it was and disconnects without if the stream ended before.
+    pub fn create_stream(
+        self,
+        rx: Receiver<Decoded>,
+        events: Sender<PlayerEvent>,
+        preroll: Sender<()>,
+    ) -> VideoStream {
+        let mut presenter = Presenter {
+            width: self.width,
+            height: self.height,
+            sink: self.sink,
+            position: Arc::new(AtomicCell::new(Duration::from_secs(0))),
+            current: Arc::new(Mutex::new(None)),
+            events,
+            preroll: Some(preroll),
+        };
+        let position = presenter.position.clone();
+        let current = presenter.current.clone();
+        let state = Arc::new(AtomicCell::new(StreamState::Paused));
         let state2 = state.clone();
-        thread::spawn(move || {
+        let speed = Arc::new(AtomicCell::new(1.0));
+        let speed2 = speed.clone();
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended2 = ended.clone();
+        let steps = Arc::new(AtomicUsize::new(0));
+        let steps2 = steps.clone();
+        let seek = Arc::new(AtomicU64::new(0));
+        let requested = seek.clone();
+        let clock = self.clock;
+        let thread = thread::spawn(move || {
             let mut prev_pts = None;
-            let mut now = Instant::now();
+            let mut now = clock.now();
+            // Seek whose flush arrived and generation of the last seek that
+            // presented a frame.
+            let mut seek: Option<Seek> = None;
+            let mut presented = 0;
+            // Last frame before the target of a backward step.
+            let mut candidate: Option<(ArcFrame, Duration)> = None;
+            // Received while looking for the candidate.
+            let mut next: Option<Decoded> = None;
             loop {
+                let seeking = presented < requested.load(Ordering::Relaxed);
+                let stepping = steps2.load(Ordering::Relaxed) > 0;
                 match state2.load() {
-                    PlayerState::Playing => {}
-                    PlayerState::Paused => {
-                        thread::sleep(Duration::from_millis(100));
+                    StreamState::Playing => {}
+                    StreamState::Paused if seeking || stepping || presenter.prerolling() => {}
+                    StreamState::Paused => {
+                        thread::sleep(POLL_INTERVAL);
                         continue;
                     }
-                    PlayerState::Stopped => break,
+                    StreamState::Stopped => break,
                 }
 
-                if let Ok(frame) = rx.recv() {
-                    let pts = frame.t.pts.unwrap();
-                    let timebase = frame.t.timebase.unwrap();
-                    let pts = Rational64::from_integer(pts * 1_000_000_000);
-                    let pts = (pts * timebase).to_integer();
-                    if let Some(prev) = prev_pts {
-                        let elapsed = now.elapsed();
-                        if pts > prev {
-                            let sleep_time = Duration::new(0, (pts - prev) as u32);
-                            if elapsed < sleep_time {
-                                log::trace!(
-                                    "Sleep for {} - {:?}",
-                                    pts - prev,
-                                    sleep_time - elapsed
-                                );
-                                thread::sleep(sleep_time - elapsed);
+                let decoded = match next.take() {
+                    Some(decoded) => decoded,
+                    None => match rx.recv_timeout(POLL_INTERVAL) {
+                        Ok(decoded) => decoded,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        // The decoder stopped.
+                        Err(RecvTimeoutError::Disconnected) => {
+                            ended2.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                    },
+                };
+                let frame = match decoded {
+                    Decoded::Frame(frame) => frame,
+                    Decoded::Flush(flush) => {
+                        seek = Some(flush);
+                        candidate = None;
+                        prev_pts = None;
+                        ended2.store(false, Ordering::Relaxed);
+                        continue;
+                    }
+                    Decoded::End => {
+                        // A seek past the last frame, or a backward step to
+                        // it.
+                        if let Some(flush) = seek.take() {
+                            if let Some((frame, pts)) = candidate.take() {
+                                presenter.present(&frame, pts, true);
                             }
+                            presented = flush.generation;
                         }
+                        steps2.store(0, Ordering::Relaxed);
+                        ended2.store(true, Ordering::Relaxed);
+                        presenter.preroll = None;
+                        continue;
                     }
-                    now = Instant::now();
+                };
+                let pts = timestamp(frame.t.pts, frame.t.timebase).unwrap_or_default();
+
+                if let Some(flush) = seek {
+                    let (frame, pts) = match flush.target {
+                        SeekTarget::At(time) if pts < time => continue,
+                        SeekTarget::Before(time) if pts < time => {
+                            candidate = Some((frame, pts));
+                            continue;
+                        }
+                        SeekTarget::At(_) => (frame, pts),
+                        SeekTarget::Before(_) => match candidate.take() {
+                            Some(candidate) => {
+                                next = Some(Decoded::Frame(frame));
+                                candidate
+                            }
+                            None => (frame, pts),
+                        },
+                    };
+                    presenter.present(&frame, pts, true);
+                    presented = flush.generation;
+                    seek = None;
                     prev_pts = Some(pts);
+                    now = clock.now();
+                    continue;
+                }
+                // Frames from before the flush of a requested seek.
+                if seeking {
+                    continue;
+                }
+                if presenter.prerolling() {
+                    presenter.present(&frame, pts, false);
+                    prev_pts = Some(pts);
+                    now = clock.now();
+                    continue;
+                }
+                if stepping {
+                    steps2.fetch_sub(1, Ordering::Relaxed);
+                    presenter.present(&frame, pts, true);
+                    prev_pts = Some(pts);
+                    now = clock.now();
+                    continue;
+                }
 
-                    if let MediaKind::Video(_) = frame.kind {
-                        let y_plane: &[u8] = frame.buf.as_slice(0).unwrap();
-                        let y_stride = frame.buf.linesize(0).unwrap() as usize;
-                        let u_plane: &[u8] = frame.buf.as_slice(1).unwrap();
-                        //let u_stride = frame.buf.linesize(1).unwrap() as usize;
-                        let v_plane: &[u8] = frame.buf.as_slice(2).unwrap();
-                        //let v_stride = frame.buf.linesize(2).unwrap() as usize;
-
-                        let img = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
-                            let (cx, cy) = (x as usize, y as usize);
-                            let y = y_plane[cy * y_stride + cx] as f64;
-                            let u = u_plane[cy / 2 * width / 2 + cx / 2] as f64;
-                            let v = v_plane[cy / 2 * width / 2 + cx / 2] as f64;
-                            let r = 1.164 * (y - 16.0) + 1.596 * (v - 128.0);
-                            let g = 1.164 * (y - 16.0) - 0.391 * (u - 128.0) - 0.813 * (v - 128.0);
-                            let b = 1.164 * (y - 16.0) + 2.018 * (u - 128.0);
-                            Rgba([clamp(r), clamp(g), clamp(b), 255])
-                        });
-                        texture.post_frame_rgba(img);
+                if let Some(prev) = prev_pts {
+                    let elapsed = clock.now() - now;
+                    if pts > prev {
+                        let delta = (pts - prev).as_nanos() as f64 / speed2.load();
+                        let sleep_time = Duration::from_nanos(delta as u64);
+                        if elapsed < sleep_time {
+                            log::trace!("Sleep for {:?} - {:?}", pts - prev, sleep_time - elapsed);
+                            clock.sleep(sleep_time - elapsed);
+                        }
                     }
                 }
+                now = clock.now();
+                prev_pts = Some(pts);
+                presenter.present(&frame, pts, false);
+            }
+        });
+        VideoStream {
+            state,
+            speed,
+            ended,
+            position,
+            current,
+            steps,
+            seek,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Posts frames to the sink of a video thread.
+struct Presenter {
+    width: usize,
+    height: usize,
+    sink: Box<dyn FrameSink>,
+    position: Arc<AtomicCell<Duration>>,
+    current: Arc<Mutex<Option<ArcFrame>>>,
+    events: Sender<PlayerEvent>,
+    /// Taken once the first frame was presented.
+    preroll: Option<Sender<()>>,
+}
+
+impl Presenter {
+    /// Whether no frame was presented yet.
+    fn prerolling(&self) -> bool {
+        self.preroll.is_some()
+    }
+
+    /// Posts `frame`, `report` sends its position as an event.
+    fn present(&mut self, frame: &ArcFrame, pts: Duration, report: bool) {
+        if let MediaKind::Video(info) = &frame.kind {
+            if (info.width, info.height) != (self.width, self.height) {
+                self.width = info.width;
+                self.height = info.height;
+                let event = PlayerEvent::SizeChanged {
+                    width: self.width as _,
+                    height: self.height as _,
+                };
+                self.events.send(event).ok();
             }
+        }
+        if let Some(img) = frame_to_rgba(frame, self.width, self.height) {
+            self.sink.post_frame(img, pts);
+        }
+        self.position.store(pts);
+        *self.current.lock().unwrap() = Some(frame.clone());
+        if let Some(preroll) = self.preroll.take() {
+            preroll.try_send(()).ok();
+        }
+        if report {
+            self.events.send(PlayerEvent::Position(pts)).ok();
+        }
+    }
+}
+
+/// Converts a decoded I420 frame of any size to RGBA, returns `None` for
+/// other frames.
+pub fn frame_image(frame: &ArcFrame) -> Option<RgbaImage> {
+    match &frame.kind {
+        MediaKind::Video(info) => frame_to_rgba(frame, info.width, info.height),
+        _ => None,
+    }
+}
+
+/// Converts a decoded I420 frame to RGBA, returns `None` for other frames.
+pub fn frame_to_rgba(frame: &ArcFrame, width: usize, height: usize) -> Option<RgbaImage> {
+    if let MediaKind::Video(_) = frame.kind {
+        let y_plane: &[u8] = frame.buf.as_slice(0).unwrap();
+        let y_stride = frame.buf.linesize(0).unwrap() as usize;
+        let u_plane: &[u8] = frame.buf.as_slice(1).unwrap();
+        let u_stride = frame.buf.linesize(1).unwrap() as usize;
+        let v_plane: &[u8] = frame.buf.as_slice(2).unwrap();
+        let v_stride = frame.buf.linesize(2).unwrap() as usize;
+
+        let img = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            let (cx, cy) = (x as usize, y as usize);
+            let y = y_plane[cy * y_stride + cx] as f64;
+            let u = u_plane[cy / 2 * u_stride + cx / 2] as f64;
+            let v = v_plane[cy / 2 * v_stride + cx / 2] as f64;
+            let r = 1.164 * (y - 16.0) + 1.596 * (v - 128.0);
+            let g = 1.164 * (y - 16.0) - 0.391 * (u - 128.0) - 0.813 * (v - 128.0);
+            let b = 1.164 * (y - 16.0) + 2.018 * (u - 128.0);
+            Rgba([clamp(r), clamp(g), clamp(b), 255])
         });
-        VideoStream { state }
+        Some(img)
+    } else {
+        None
     }
 }
 
@@ -131,3 +447,142 @@ fn clamp(value: f64) -> u8 {
     }
     value as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use av_data::frame::{new_default_frame, PictureType, TimeInfo, VideoInfo as FrameVideoInfo};
+    use av_data::pixel::formats::YUV420;
+    use av_data::rational::Rational64;
+    use std::sync::mpsc;
+
+    /// Creates an I420 frame, `sample(plane, x, y)` gives the value of each
+    /// sample.
+    fn i420_frame<F>(width: usize, height: usize, pts_ms: i64, sample: F) -> ArcFrame
+    where
+        F: Fn(usize, usize, usize) -> u8,
+    {
+        let info = FrameVideoInfo {
+            pic_type: PictureType::I,
+            width,
+            height,
+            format: Arc::new(*YUV420),
+        };
+        let time = TimeInfo {
+            pts: Some(pts_ms),
+            timebase: Some(Rational64::new(1, 1000)),
+            ..TimeInfo::default()
+        };
+        let mut frame = new_default_frame(MediaKind::Video(info), Some(time));
+        for plane in 0..3 {
+            let (w, h) = if plane == 0 {
+                (width, height)
+            } else {
+                ((width + 1) / 2, (height + 1) / 2)
+            };
+            let stride = frame.buf.linesize(plane).unwrap() as usize;
+            let data: &mut [u8] = frame.buf.as_mut_slice(plane).unwrap();
+            for y in 0..h {
+                for x in 0..w {
+                    data[y * stride + x] = sample(plane, x, y);
+                }
+            }
+        }
+        Arc::new(frame)
+    }
+
+    #[test]
+    fn converts_i420_to_rgba() {
+        // White on the left, red on the right, in studio range BT.601.
+        let frame = i420_frame(8, 4, 0, |plane, x, _| {
+            let right = if plane == 0 { x >= 4 } else { x >= 2 };
+            match (plane, right) {
+                (0, false) => 235,
+                (_, false) => 128,
+                (0, true) => 81,
+                (1, true) => 90,
+                (_, true) => 240,
+            }
+        });
+        let image = frame_to_rgba(&frame, 8, 4).unwrap();
+        assert_eq!(image.dimensions(), (8, 4));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let expected = if x < 4 {
+                Rgba([254, 254, 254, 255])
+            } else {
+                Rgba([254, 0, 0, 255])
+            };
+            assert_eq!(*pixel, expected, "pixel at {}, {}", x, y);
+        }
+        assert_eq!(frame_image(&frame), Some(image));
+    }
+
+    /// Passes time only when slept, and records the sleeps.
+    struct ManualClock {
+        start: Instant,
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + self.sleeps.lock().unwrap().iter().sum()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+        }
+    }
+
+    /// Plays `count` frames 40 ms apart at `speed` and returns the pts of
+    /// the frames posted after the first one and the time slept before each.
+    fn play_frames(count: i64, speed: f64) -> (Vec<u128>, Vec<Duration>) {
+        let sink = MemoryFrameSink::new();
+        let clock = Arc::new(ManualClock {
+            start: Instant::now(),
+            sleeps: Mutex::new(Vec::new()),
+        });
+        let player = VideoPlayer {
+            width: 2,
+            height: 2,
+            sink: Box::new(sink.clone()),
+            clock: clock.clone(),
+        };
+        let (tx, rx) = mpsc::channel();
+        let (events, _events) = crossbeam::channel::unbounded();
+        let (preroll, prerolled) = crossbeam::channel::bounded(1);
+        for n in 0..count {
+            let frame = i420_frame(2, 2, n * 40, |_, _, _| 128);
+            tx.send(Decoded::Frame(frame)).unwrap();
+        }
+        drop(tx);
+        let stream = player.create_stream(rx, events, preroll);
+        prerolled.recv().ok();
+        assert_eq!(sink.len(), 1);
+        stream.set_speed(speed);
+        stream.play();
+        let ended = stream.ended();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !ended.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(stream);
+        let frames = sink.frames().split_off(1);
+        let pts = frames.iter().map(|frame| frame.pts.as_millis()).collect();
+        let sleeps = clock.sleeps.lock().unwrap().clone();
+        (pts, sleeps)
+    }
+
+    #[test]
+    fn paces_frames_by_pts() {
+        let (pts, sleeps) = play_frames(10, 1.0);
+        assert_eq!(pts, (1..10).map(|n| n * 40).collect::<Vec<_>>());
+        assert_eq!(sleeps, vec![Duration::from_millis(40); 9]);
+    }
+
+    #[test]
+    fn paces_frames_by_speed() {
+        let (pts, sleeps) = play_frames(10, 2.0);
+        assert_eq!(pts.len(), 9);
+        assert_eq!(sleeps, vec![Duration::from_millis(20); 9]);
+    }
+}
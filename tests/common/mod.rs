@@ -0,0 +1,28 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+use crossbeam::channel::Receiver;
+use flutter_video_plugin::PlayerEvent;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Two seconds of 64x36 VP9 video at 25 fps, every frame a keyframe, and
+/// silent stereo Opus audio at 48 kHz, see `tests/fixtures/make_sample.py`.
+pub fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.webm")
+}
+
+/// Waits for an event `matches` accepts and returns it, panics on errors
+/// and if no event arrives for 10 seconds.
+pub fn wait_for(
+    events: &Receiver<PlayerEvent>,
+    matches: impl Fn(&PlayerEvent) -> bool,
+) -> PlayerEvent {
+    loop {
+        match events.recv_timeout(Duration::from_secs(10)) {
+            Ok(event) if matches(&event) => return event,
+            Ok(PlayerEvent::Error(message)) => panic!("playback failed: {}", message),
+            Ok(_) => {}
+            Err(err) => panic!("no event: {}", err),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Checks that disposed players don't leave threads or files behind.
+//!
+//! Plays `tests/fixtures/sample.webm`, or the file in `VIDEO_TEST_FILE` if
+//! set.
+#![cfg(target_os = "linux")]
+mod common;
+
+use flutter_video_plugin::{AudioSink, MemoryFrameSink, Player, PlayerEvent};
+use std::fs;
+use std::path::PathBuf;
+
+const PLAYERS: usize = 1000;
+
+fn thread_count() -> usize {
+    fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+fn file_count() -> usize {
+    fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+#[test]
+fn dispose_releases_threads_and_files() {
+    let path = std::env::var_os("VIDEO_TEST_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(common::fixture);
+    let threads = thread_count();
+    let files = file_count();
+    for _ in 0..PLAYERS {
+        let player = Player::builder()
+            .path(&path)
+            .frame_sink(MemoryFrameSink::new())
+            .audio_sink(AudioSink::Null { realtime: false })
+            .build()
+            .unwrap();
+        let events = player.events();
+        while let Ok(event) = events.recv() {
+            if let PlayerEvent::Initialized { .. } = event {
+                break;
+            }
+        }
+        player.play().unwrap();
+        player.dispose();
+        drop(player);
+        // Every sender is gone once the threads were joined.
+        while events.recv().is_ok() {}
+    }
+    assert_eq!(thread_count(), threads);
+    assert_eq!(file_count(), files);
+}
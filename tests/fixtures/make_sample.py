@@ -0,0 +1,162 @@
+#!/usr/bin/env python3
+"""Writes sample.webm, two seconds of VP9 video and Opus audio.
+
+No encoder is needed: every VP9 frame is a keyframe whose compressed header
+and tile data are zero bytes, which the boolean decoder reads as all zero
+syntax elements, giving a uniform gray picture. Every Opus packet is a bare
+TOC byte, which decodes to 20 ms of silence.
+"""
+import struct
+from pathlib import Path
+
+WIDTH, HEIGHT = 64, 36
+FPS = 25
+SECONDS = 2
+RATE = 48_000
+CHANNELS = 2
+OPUS_FRAME_MS = 20
+
+
+def vint(value):
+    """EBML variable length integer, in the shortest form."""
+    for length in range(1, 9):
+        if value < (1 << (7 * length)) - 1:
+            return ((1 << (7 * length)) | value).to_bytes(length, "big")
+    raise ValueError(value)
+
+
+def element(id, body):
+    id_bytes = id.to_bytes((id.bit_length() + 7) // 8, "big")
+    return id_bytes + vint(len(body)) + body
+
+
+def uint(id, value):
+    return element(id, value.to_bytes(max(1, (value.bit_length() + 7) // 8), "big"))
+
+
+def float64(id, value):
+    return element(id, struct.pack(">d", value))
+
+
+def string(id, value):
+    return element(id, value.encode())
+
+
+class BitWriter:
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, count):
+        self.bits += [(value >> i) & 1 for i in reversed(range(count))]
+
+    def bytes(self):
+        bits = self.bits + [0] * (-len(self.bits) % 8)
+        return bytes(
+            int("".join(map(str, bits[i : i + 8])), 2) for i in range(0, len(bits), 8)
+        )
+
+
+def vp9_keyframe():
+    header_size = 16
+    w = BitWriter()
+    w.put(2, 2)  # frame_marker
+    w.put(0, 2)  # profile 0
+    w.put(0, 1)  # show_existing_frame
+    w.put(0, 1)  # frame_type, key frame
+    w.put(1, 1)  # show_frame
+    w.put(0, 1)  # error_resilient_mode
+    w.put(0x498342, 24)  # frame_sync_code
+    w.put(1, 3)  # color_space, BT.601
+    w.put(0, 1)  # color_range, studio
+    w.put(WIDTH - 1, 16)
+    w.put(HEIGHT - 1, 16)
+    w.put(0, 1)  # render_and_frame_size_different
+    w.put(1, 1)  # refresh_frame_context
+    w.put(1, 1)  # frame_parallel_decoding_mode
+    w.put(0, 2)  # frame_context_idx
+    w.put(0, 6)  # loop_filter_level
+    w.put(0, 3)  # loop_filter_sharpness
+    w.put(0, 1)  # loop_filter_delta_enabled
+    w.put(60, 8)  # base_q_idx
+    w.put(0, 3)  # no delta_q for y dc, uv dc and uv ac
+    w.put(0, 1)  # segmentation_enabled
+    w.put(0, 1)  # tile_rows_log2, one tile column is implied by the width
+    w.put(header_size, 16)
+    # Zero compressed header: 4x4 transforms, no probability updates. Zero
+    # tile data: one 64x64 block, DC prediction, no coefficients.
+    return w.bytes() + bytes(header_size) + bytes(512)
+
+
+def opus_packet():
+    # Config 31 (CELT fullband 20 ms), stereo, one frame, and no frame data.
+    return bytes([31 << 3 | 1 << 2 | 0])
+
+
+def opus_head():
+    return b"OpusHead" + struct.pack("<BBHIhB", 1, CHANNELS, 312, RATE, 0, 0)
+
+
+def simple_block(track, timecode, data):
+    # Keyframe flag set, no lacing.
+    return element(0xA3, vint(track) + struct.pack(">hB", timecode, 0x80) + data)
+
+
+def main():
+    ebml = element(
+        0x1A45DFA3,
+        uint(0x4286, 1)  # EBMLVersion
+        + uint(0x42F7, 1)  # EBMLReadVersion
+        + uint(0x42F2, 4)  # EBMLMaxIDLength
+        + uint(0x42F3, 8)  # EBMLMaxSizeLength
+        + string(0x4282, "webm")  # DocType
+        + uint(0x4287, 4)  # DocTypeVersion
+        + uint(0x4285, 2),  # DocTypeReadVersion
+    )
+    info = element(
+        0x1549A966,
+        uint(0x2AD7B1, 1_000_000)  # TimecodeScale, milliseconds
+        + float64(0x4489, SECONDS * 1000.0)  # Duration
+        + string(0x4D80, "make_sample.py")  # MuxingApp
+        + string(0x5741, "make_sample.py"),  # WritingApp
+    )
+    video = element(
+        0xAE,
+        uint(0xD7, 1)  # TrackNumber
+        + uint(0x73C5, 1)  # TrackUID
+        + uint(0x83, 1)  # TrackType, video
+        + uint(0x9C, 0)  # FlagLacing
+        + string(0x86, "V_VP9")  # CodecID
+        + uint(0x23E383, 1_000_000_000 // FPS)  # DefaultDuration
+        + element(0xE0, uint(0xB0, WIDTH) + uint(0xBA, HEIGHT)),
+    )
+    audio = element(
+        0xAE,
+        uint(0xD7, 2)
+        + uint(0x73C5, 2)
+        + uint(0x83, 2)  # TrackType, audio
+        + uint(0x9C, 0)
+        + string(0x86, "A_OPUS")
+        + element(0x63A2, opus_head())  # CodecPrivate
+        + element(0xE1, float64(0xB5, float(RATE)) + uint(0x9F, CHANNELS)),
+    )
+    tracks = element(0x1654AE6B, video + audio)
+
+    clusters = b""
+    frame = vp9_keyframe()
+    for second in range(SECONDS):
+        start = second * 1000
+        blocks = [(t, 1, frame) for t in range(0, 1000, 1000 // FPS)]
+        blocks += [(t, 2, opus_packet()) for t in range(0, 1000, OPUS_FRAME_MS)]
+        blocks.sort(key=lambda block: (block[0], block[1]))
+        body = uint(0xE7, start)  # Timecode
+        for t, track, data in blocks:
+            body += simple_block(track, t, data)
+        clusters += element(0x1F43B675, body)
+
+    segment = element(0x18538067, info + tracks + clusters)
+    path = Path(__file__).with_name("sample.webm")
+    path.write_bytes(ebml + segment)
+
+
+if __name__ == "__main__":
+    main()
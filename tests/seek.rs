@@ -0,0 +1,108 @@
+//! Checks initialization and the frames presented by seeks and steps while
+//! paused.
+mod common;
+
+use common::{fixture, wait_for};
+use flutter_video_plugin::{AudioSink, MemoryFrameSink, Player, PlayerEvent};
+use std::time::Duration;
+
+fn paused_player() -> (Player, MemoryFrameSink) {
+    let sink = MemoryFrameSink::new();
+    let player = Player::builder()
+        .path(fixture())
+        .frame_sink(sink.clone())
+        .audio_sink(AudioSink::Null { realtime: false })
+        .build()
+        .unwrap();
+    wait_for(&player.events(), |event| {
+        matches!(event, PlayerEvent::Initialized { .. })
+    });
+    (player, sink)
+}
+
+/// Waits for the position reported after a seek or step, checks that it's
+/// the timestamp of the frame posted last and returns it in milliseconds.
+fn presented(player: &Player, sink: &MemoryFrameSink) -> u128 {
+    let event = wait_for(&player.events(), |event| {
+        matches!(event, PlayerEvent::Position(_))
+    });
+    let position = match event {
+        PlayerEvent::Position(position) => position,
+        _ => unreachable!(),
+    };
+    let posted = sink.frames().last().map(|frame| frame.pts);
+    assert_eq!(posted, Some(position));
+    position.as_millis()
+}
+
+#[test]
+fn pause_while_initializing_still_initializes() {
+    let player = Player::builder()
+        .path(fixture())
+        .frame_sink(MemoryFrameSink::new())
+        .audio_sink(AudioSink::Null { realtime: false })
+        .build()
+        .unwrap();
+    // Fails unless preroll already finished, either way initialization
+    // completes.
+    player.pause().ok();
+    wait_for(&player.events(), |event| {
+        matches!(event, PlayerEvent::Initialized { .. })
+    });
+    player.play().unwrap();
+}
+
+#[test]
+fn first_frame_is_presented_while_paused() {
+    let (_player, sink) = paused_player();
+    let frames = sink.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].pts, Duration::from_millis(0));
+    assert_eq!(frames[0].image.dimensions(), (64, 36));
+}
+
+#[test]
+fn seek_to_presents_the_frame_at_the_target() {
+    let (player, sink) = paused_player();
+    player.seek_to(1000).unwrap();
+    assert_eq!(presented(&player, &sink), 1000);
+    // Between frames, the next one is presented.
+    player.seek_to(1010).unwrap();
+    assert_eq!(presented(&player, &sink), 1040);
+    player.seek_to(200).unwrap();
+    assert_eq!(presented(&player, &sink), 200);
+}
+
+#[test]
+fn steps_present_neighbouring_frames() {
+    let (player, sink) = paused_player();
+    player.step_forward().unwrap();
+    assert_eq!(presented(&player, &sink), 40);
+    player.step_forward().unwrap();
+    assert_eq!(presented(&player, &sink), 80);
+    player.step_backward().unwrap();
+    assert_eq!(presented(&player, &sink), 40);
+    player.seek_to(1500).unwrap();
+    assert_eq!(presented(&player, &sink), 1520);
+    player.step_backward().unwrap();
+    assert_eq!(presented(&player, &sink), 1480);
+    player.step_forward().unwrap();
+    assert_eq!(presented(&player, &sink), 1520);
+}
+
+#[test]
+fn newest_of_several_seeks_wins() {
+    let (player, sink) = paused_player();
+    for time in &[400, 800, 1200] {
+        player.seek_to(*time).unwrap();
+    }
+    let events = player.events();
+    let mut last = None;
+    while last != Some(Duration::from_millis(1200)) {
+        match wait_for(&events, |event| matches!(event, PlayerEvent::Position(_))) {
+            PlayerEvent::Position(position) => last = Some(position),
+            _ => unreachable!(),
+        }
+    }
+    assert_eq!(sink.frames().last().map(|frame| frame.pts), last);
+}
@@ -0,0 +1,27 @@
+//! Checks thumbnails generated from the fixture.
+mod common;
+
+use common::fixture;
+use flutter_video_plugin::{thumbnails, ImageFormat};
+use std::time::Duration;
+
+#[test]
+fn thumbnails_are_returned_in_request_order() {
+    let times: Vec<Duration> = [1500, 0, 1500, 5000, 40]
+        .iter()
+        .map(|&ms| Duration::from_millis(ms))
+        .collect();
+    let images = thumbnails(&fixture(), &times, ImageFormat::Png, Some(32)).unwrap();
+    assert_eq!(images.len(), times.len());
+    for bytes in &images {
+        let image = image::load_from_memory(bytes).unwrap().to_rgba();
+        assert_eq!(image.dimensions(), (32, 18));
+    }
+    assert_eq!(images[0], images[2]);
+}
+
+#[test]
+fn thumbnails_of_no_times_are_empty() {
+    let images = thumbnails(&fixture(), &[], ImageFormat::Jpeg, None).unwrap();
+    assert!(images.is_empty());
+}
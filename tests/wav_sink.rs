@@ -0,0 +1,62 @@
+//! Plays the fixture to a WAV file and checks what was written.
+mod common;
+
+use common::{fixture, wait_for};
+use flutter_video_plugin::{AudioSink, MemoryFrameSink, Player, PlayerEvent};
+use std::fs;
+
+/// Channels, rate and length of the fixture's audio.
+const CHANNELS: usize = 2;
+const RATE: usize = 48_000;
+const FRAMES: usize = 2 * RATE;
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    let mut field = [0; 4];
+    field.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(field) as usize
+}
+
+#[test]
+fn plays_to_wav() {
+    let wav = std::env::temp_dir().join(format!("{}-plays_to_wav.wav", std::process::id()));
+    let player = Player::builder()
+        .path(fixture())
+        .frame_sink(MemoryFrameSink::new())
+        .audio_sink(AudioSink::Wav {
+            path: wav.clone(),
+            realtime: false,
+        })
+        .build()
+        .unwrap();
+    let events = player.events();
+    wait_for(&events, |event| {
+        matches!(event, PlayerEvent::Initialized { .. })
+    });
+    player.play().unwrap();
+    wait_for(&events, |event| *event == PlayerEvent::Completed);
+    // The header sizes are written once the sink stops.
+    drop(player);
+
+    let bytes = fs::read(&wav).unwrap();
+    fs::remove_file(&wav).ok();
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(read_u32(&bytes, 4), bytes.len() - 8);
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(
+        u16::from_le_bytes([bytes[22], bytes[23]]) as usize,
+        CHANNELS
+    );
+    assert_eq!(read_u32(&bytes, 24), RATE);
+    assert_eq!(&bytes[36..40], b"data");
+    let data_len = read_u32(&bytes, 40);
+    assert_eq!(data_len, bytes.len() - 44);
+    let block_align = CHANNELS * 2;
+    assert_eq!(data_len % block_align, 0);
+    let frames = data_len / block_align;
+    assert!(
+        frames > FRAMES * 95 / 100 && frames <= FRAMES,
+        "{} frames written, expected about {}",
+        frames,
+        FRAMES
+    );
+}